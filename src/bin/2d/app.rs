@@ -4,25 +4,29 @@ use fstop::core::engine::WindowState;
 use fstop::create_render_pass;
 use fstop::core::model::{
     Material,
-    Mesh
+    Mesh,
+    Instances,
+    MorphWeights,
 };
+use fstop::core::batch::RenderQueue;
 use fstop::core::texture::Texture;
-use fstop::core::camera::{ 
-    Camera, 
-    CameraController,
-    Projection
+use fstop::core::skeleton::Skin;
+use fstop::core::camera::Camera;
+use fstop::core::input::{ self, Input, InputEvent };
+use fstop::core::controller::{
+    PanZoomController,
+    FpsController,
 };
 use fstop::core::renderer::{ 
     Framebuffer, 
-    Draw,
     BindGroupLayouts,
     RenderPipelineWrapper,
     PipelineResources,
     PipelineBuffers,
-    Resource, VertexUV,
+    DepthMode,
+    Resource,
     SCREENQUAD,
     SCREENQUAD_INDICES,
-    VertexBuffer,
 };
 
 use winit::window::Window;
@@ -45,7 +49,10 @@ pub struct Engine
     framebuffer : Framebuffer,
     world : world::World,
     screenquad : Mesh,
-    mouse_locked: bool
+    batch_instances : Instances,
+    mouse_locked: bool,
+    input : Input,
+    pub quit : bool,
 } 
 
 
@@ -62,6 +69,8 @@ impl Engine
             camera: Camera::desc(&device),
             material: Material::desc(&device),
             framebuffer: Framebuffer::desc(&device),
+            skin: Skin::desc(&device),
+            morph: MorphWeights::desc(&device),
         };
 
 
@@ -74,7 +83,7 @@ impl Engine
                 depth_or_array_layers: 1,
             };
             let texture = Texture::create_blank_texture(&device, size,"low-res-texture", wgpu::FilterMode::Nearest);
-            let depth_texture = Texture::create_depth_texture(&device, size, "depth_texture", wgpu::FilterMode::Nearest);
+            let depth_texture = Texture::create_depth_texture(&device, size, "depth_texture", wgpu::FilterMode::Nearest, DepthMode::Standard);
             let bind_group = Some(Framebuffer::make_bind_group(&device, &layouts,&texture,  &depth_texture));
             framebuffer = Framebuffer
             {
@@ -91,7 +100,7 @@ impl Engine
                 &config,
                 &shader,
                 // &rayshader,
-                false,
+                None,
                 vec![PipelineResources::Camera , PipelineResources::Material],
                 vec![PipelineBuffers::VertexUV, PipelineBuffers::Instance],
                 &layouts,
@@ -101,20 +110,23 @@ impl Engine
 
 
         let world = world::World::new(&device, &queue, &layouts ).await;
+        let input = Input::new(input::load_input_map("input.ron").await.unwrap());
         let camera = Camera::new(
-            cgmath::Point3::new(0.0, -10.0, 0.0),
-            cgmath::Deg(0.0),
-            cgmath::Deg(0.0),
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0),
-            CameraController::new(10.0, 0.2),
+            world.camera.position(),
+            world.camera.yaw(),
+            world.camera.pitch(),
+            world.camera.projection(config.width, config.height),
+            vec![
+                Box::new(PanZoomController::new(10.0)),
+                Box::new(FpsController::new(10.0, 0.2)),
+            ],
             &device,
             &layouts.camera,
 
         );
 
-        let screenquad_buffer = VertexUV::new_vertex_buffer(&device, &SCREENQUAD);
-        let screenquad_index_buffer = VertexUV::new_index_buffer(&device, &SCREENQUAD_INDICES);
-        let screenquad = Mesh { name: "screenquad".to_string(), vertex_buffer: screenquad_buffer, index_buffer: screenquad_index_buffer, num_elements: 6 };
+        let screenquad = Mesh::new(&device, "screenquad", SCREENQUAD.to_vec(), SCREENQUAD_INDICES.to_vec());
+        let batch_instances = Instances::new(&device, Vec::new());
 
         Self
         {
@@ -127,7 +139,10 @@ impl Engine
             framebuffer,
             world,
             screenquad,
+            batch_instances,
             mouse_locked: false,
+            input,
+            quit: false,
         }
     } // end new }}}
 
@@ -168,7 +183,8 @@ impl Engine
                     depth_or_array_layers: 1,
                 },
                 "depth_texture",
-                wgpu::FilterMode::Nearest));
+                wgpu::FilterMode::Nearest,
+                DepthMode::Standard));
             
             self.framebuffer.bind_group = Some(Framebuffer::make_bind_group(&self.device, &self.layouts, self.framebuffer.texture.as_ref().unwrap(), 
                 self.framebuffer.depth_texture.as_ref().unwrap()));
@@ -181,57 +197,14 @@ impl Engine
     { 
         match event 
         {
-            WindowEvent::KeyboardInput {
-                    input: KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Tab),
-                        ..
-                    },
-                    ..
-            } => 
-            {
-                if self.mouse_locked == false
-                {
-                    self.window_state.window.set_cursor_grab(CursorGrabMode::Confined).or_else(|_| 
-                        self.window_state.window.set_cursor_grab(CursorGrabMode::Locked)).unwrap();
-                    self.window_state.window.set_cursor_visible(false);
-                    self.mouse_locked = true; 
-                }
-                else
-                {
-                    self.window_state.window.set_cursor_grab(CursorGrabMode::None).unwrap();
-                    self.window_state.window.set_cursor_visible(true);
-                    self.mouse_locked = false;
-                }
-                true
-            }
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        virtual_keycode: Some(key),
-                        state,
-                        ..
-                    },
-                ..
-            } => 
-            { 
-                self.camera.controller.process_keyboard(*key, *state);
-                // self.window.set_title(&format!("{:?}", self.camera.position));
-                true
-            }
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.camera.controller.process_scroll(delta);
-                // self.window.set_title(&format!("{:?}", self.camera.position));
-                true
-            }
             WindowEvent::CursorMoved { position, .. } if self.mouse_locked == false => 
             {
                 let x = position.x as f64 / self.window_state.size.width as f64;
                 let y = position.y as f64 / self.window_state.size.height as f64;
-                self.camera.controller.process_mouse_pos(x, y);
+                self.camera.input.process_mouse_pos(x, y);
                 true
             }
-            _ => false,
+            _ => InputEvent::from_window(event, self.window_state.size).is_some_and(|event| self.input.handle(&event)),
         }
     } // end window input }}}
 
@@ -243,7 +216,7 @@ impl Engine
         {
             DeviceEvent::MouseMotion{ delta, } if self.mouse_locked == true => 
             {
-                self.camera.controller.process_mouse(delta.0, delta.1);
+                self.camera.input.process_mouse(delta.0, delta.1);
                 true
             }
             _ => false,
@@ -251,26 +224,70 @@ impl Engine
     } // end device input }}}
 
 
+// actions {{{
+    // the one shot actions of assets/input.ron, the camera reads movement on its own
+    fn actions(&mut self)
+    {
+        if self.input.pressed("quit")
+        {
+            self.quit = true;
+        }
+        if self.input.pressed("toggle_cursor")
+        {
+            if self.mouse_locked == false
+            {
+                self.window_state.window.set_cursor_grab(CursorGrabMode::Confined).or_else(|_| 
+                    self.window_state.window.set_cursor_grab(CursorGrabMode::Locked)).unwrap();
+                self.window_state.window.set_cursor_visible(false);
+                self.mouse_locked = true; 
+            }
+            else
+            {
+                self.window_state.window.set_cursor_grab(CursorGrabMode::None).unwrap();
+                self.window_state.window.set_cursor_visible(true);
+                self.mouse_locked = false;
+            }
+        }
+        if self.input.pressed("next_controller")
+        {
+            log::info!("camera controller: {}", self.camera.next_controller().name());
+        }
+        self.camera.input.read_actions(&self.input);
+        self.input.end_frame();
+    } // end actions }}}
+
+
 // update {{{
     pub fn update(&mut self, dt: instant::Duration, time: instant::Instant)
     { 
-        // self.camera.update_orbit(dt);
-        // self.camera.update_fps(dt);
-        self.camera.update_2d(dt);
-        self.camera.update_view_proj();
-        self.queue.write_buffer(&self.camera.buffer, 0, bytemuck::cast_slice(&[self.camera.uniform]));
+        self.actions();
+        self.camera.update(dt);
+
+        self.world.graph.update();
+        self.world.graph.upload(&self.device, &self.queue, "sphere", &mut self.world.sphere_instances);
+        self.world.graph.upload(&self.device, &self.queue, "cube", &mut self.world.cube_instances);
+        self.world.graph.upload(&self.device, &self.queue, "plane", &mut self.world.plane_instances);
 
-        // self.world.sphere_instances.instances[0].position = cgmath::Vector3::new(0.0, 0.0, t * 1.0); 
-        // let instance_data = self.world.sphere_instances.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        // self.queue.write_buffer(&self.world.sphere_instances.buffer, 0, bytemuck::cast_slice(&instance_data));
+        // if let Some(sphere) = self.world.sphere_instances.get_mut(0) { sphere.position = cgmath::Vector3::new(0.0, 0.0, t * 1.0); }
+        // self.world.sphere_instances.upload(&self.device, &self.queue);
     } // end update }}}
 
 
 // render {{{
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> 
+    pub fn render(&mut self, alpha: f32) -> Result<(), wgpu::SurfaceError> 
     { 
         let output = self.window_state.surface.get_current_texture()?;
+        // draw the camera alpha of the way between the last two ticks, the simulation keeps its own state
+        let interpolated = self.camera.interpolated(alpha);
+        let state = std::mem::replace(&mut self.camera.state, interpolated);
+        self.camera.update_view_proj();
+        self.queue.write_buffer(&self.camera.buffer, 0, bytemuck::cast_slice(&[self.camera.uniform]));
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut render_queue = RenderQueue::new();
+        render_queue.set_frustum(self.camera.frustum());
+        render_queue.submit_instances(&self.pipeline, &self.screenquad, &self.world.mats[0], &self.world.sphere_instances);
+        render_queue.prepare(&self.device, &self.queue, &mut self.batch_instances, self.camera.state.position);
+
         let mut encoder = self.device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor 
             {
@@ -297,12 +314,10 @@ impl Engine
                     timestamp_writes: None,
                     occlusion_query_set: None,
             });
-            render_pass.set_pipeline(&self.pipeline.pipeline);
-            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
-            render_pass.set_bind_group(1, &self.world.mats[0].bind_group, &[]);
-            render_pass.draw_mesh_instanced(&self.screenquad, &self.world.sphere_instances, 0..3) ; 
+            render_queue.draw(&mut render_pass, &self.batch_instances, &self.camera.bind_group);
         }
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.camera.state = state;
         output.present();
 
         Ok(())
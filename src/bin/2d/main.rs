@@ -3,7 +3,7 @@ extern crate fstop;
 mod app;
 mod world;
 
-use fstop::engine::WindowState;
+use fstop::engine::{WindowState, FixedTimestep};
 
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
@@ -15,6 +15,11 @@ use winit::
 
 
 
+// simulation ticks per second, and the most ticks one redraw may run to catch up
+const TICK_RATE : u32 = 60;
+const MAX_STEPS : u32 = 5;
+
+
 fn main() 
 {
     pollster::block_on(run());
@@ -30,6 +35,7 @@ async fn run()
     let (device, queue, window_state) = fstop::new_device!(window);
     let mut engine = app::Engine::new(window_state, device, queue).await;
     let mut last_render_time = instant::Instant::now();
+    let mut timestep = FixedTimestep::new(TICK_RATE, MAX_STEPS);
 
     event_loop.run(
         move |event, _, control_flow| 
@@ -44,17 +50,7 @@ async fn run()
             {
                 match event 
                 {
-                    WindowEvent::CloseRequested | 
-                    WindowEvent::KeyboardInput 
-                    {
-                        input : KeyboardInput 
-                        {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        },
-                        ..
-                    } => *control_flow = ControlFlow::Exit, 
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit, 
 
                     WindowEvent::Resized(physical_size) =>  engine.resize(*physical_size),
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } =>  engine.resize(**new_inner_size),
@@ -70,8 +66,15 @@ async fn run()
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
-                engine.update(dt, last_render_time);
-                match engine.render()
+                for _ in 0..timestep.advance(dt)
+                {
+                    engine.update(timestep.step, last_render_time);
+                }
+                if engine.quit
+                {
+                    *control_flow = ControlFlow::Exit;
+                }
+                match engine.render(timestep.alpha())
                 {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
@@ -3,13 +3,29 @@ use fstop::core::engine::WindowState;
 use fstop::create_render_pass;
 use fstop::core::model::{
     Material,
-    Mesh
+    Mesh,
+    Instances,
+    MorphWeights,
 };
+use fstop::core::batch::RenderQueue;
+use fstop::core::culling::{ GpuCuller, CullTarget, HiZ };
 use fstop::core::texture::Texture;
-use fstop::core::camera::{ 
-    Camera, 
-    CameraController,
-    Projection
+use fstop::core::skeleton::Skin;
+use fstop::core::animation::{ Clock, Playback, Target };
+use fstop::core::picking::{ self, IdBuffer };
+use fstop::core::input::{ self, Input, InputEvent };
+use fstop::core::replay::{ InputRecording, InputReplay };
+use fstop::core::camera::Camera;
+use fstop::core::stats::{ FrameStats, StatsOverlay, GpuTimer };
+use fstop::core::text::{ self, TextRenderer, TextMode };
+use fstop::core::gui::{ self, Gui };
+use fstop::core::debug::{ self, DebugDraw };
+use fstop::core::camera_path::CameraPath;
+use fstop::core::controller::{
+    OrbitController,
+    FpsController,
+    ArcballController,
+    PanZoomController,
 };
 use fstop::core::renderer::{ 
     Framebuffer, 
@@ -18,18 +34,26 @@ use fstop::core::renderer::{
     RenderPipelineWrapper,
     PipelineResources,
     PipelineBuffers,
-    Resource, VertexUV,
+    DepthMode,
+    Resource,
     SCREENQUAD,
     SCREENQUAD_INDICES,
-    VertexBuffer,
 };
 
 use winit::window::Window;
 use winit::event::*;
 use winit::window::CursorGrabMode;
+use winit::dpi::PhysicalPosition;
 
 
 const PIXEL_SIZE : u32 = 1;
+const DEPTH_MODE : DepthMode = DepthMode::ReverseZ;
+// recorded camera paths go next to the working directory, R records and P plays back
+// frames kept for the timing statistics and drawn in the overlay graph
+const STATS_HISTORY : usize = 240;
+const TEXT_SIZE : f32 = 16.0;
+const CAMERA_PATH_FILE : &str = "camera_path.ron";
+const PATH_SMOOTHING : f32 = 0.25;
 
 
 pub struct Engine
@@ -42,10 +66,41 @@ pub struct Engine
     pixel_pipeline : RenderPipelineWrapper,
     floor_pipeline : RenderPipelineWrapper,
     final_pipeline : RenderPipelineWrapper,
+    skinned_pipeline : RenderPipelineWrapper,
+    morph_pipeline : RenderPipelineWrapper,
     pixelframebuffer : Framebuffer,
     world : world::World,
     screenquad : Mesh,
-    mouse_locked: bool
+    batch_instances : Instances,
+    culler : GpuCuller,
+    // one per mesh of the sphere model
+    cull_targets : Vec<CullTarget>,
+    hiz : HiZ,
+    gpu_culling : bool,
+    skin : Skin,
+    morph_weights : MorphWeights,
+    animation_time : f32,
+    recording : Option<CameraPath>,
+    replay : Option<(CameraPath, Clock)>,
+    replay_frames : u32,
+    replay_start : instant::Instant,
+    id_buffer : IdBuffer,
+    cursor : PhysicalPosition<f64>,
+    gpu_pick : Option<PhysicalPosition<f64>>,
+    mouse_locked: bool,
+    input : Input,
+    input_recording : Option<(InputRecording, String)>,
+    input_replay : Option<InputReplay>,
+    pub stats : FrameStats,
+    overlay : StatsOverlay,
+    text : TextRenderer,
+    gui : Gui,
+    debug : DebugDraw,
+    // view_proj kept by freeze_frustum, drawn until thawed
+    frozen_frustum : Option<cgmath::Matrix4<f32>>,
+    pixel_size : u32,
+    gpu_timer : GpuTimer,
+    pub quit : bool,
 } 
 
 
@@ -61,6 +116,8 @@ impl Engine
             camera: Camera::desc(&device),
             material: Material::desc(&device),
             framebuffer: Framebuffer::desc(&device),
+            skin: Skin::desc(&device),
+            morph: MorphWeights::desc(&device),
         };
 
 
@@ -73,7 +130,7 @@ impl Engine
                 depth_or_array_layers: 1,
             };
             let texture = Texture::create_blank_texture(&device, size,"low-res-texture", wgpu::FilterMode::Nearest);
-            let depth_texture = Texture::create_depth_texture(&device, size, "depth_texture", wgpu::FilterMode::Nearest);
+            let depth_texture = Texture::create_depth_texture(&device, size, "depth_texture", wgpu::FilterMode::Nearest, DEPTH_MODE);
             let bind_group = Some(Framebuffer::make_bind_group(&device, &layouts,&texture,  &depth_texture));
             pixelframebuffer = Framebuffer
             {
@@ -85,18 +142,22 @@ impl Engine
         let pixel_pipeline : RenderPipelineWrapper;
         let floor_pipeline : RenderPipelineWrapper;
         let final_pipeline : RenderPipelineWrapper;
+        let skinned_pipeline : RenderPipelineWrapper;
+        let morph_pipeline : RenderPipelineWrapper;
 
         {
             let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/shader.wgsl"));
             let floorshader = device.create_shader_module(wgpu::include_wgsl!("shaders/floor.wgsl"));
             let finalshader = device.create_shader_module(wgpu::include_wgsl!("shaders/final.wgsl"));
+            let skinnedshader = device.create_shader_module(wgpu::include_wgsl!("shaders/skinned.wgsl"));
+            let morphshader = device.create_shader_module(wgpu::include_wgsl!("shaders/morph.wgsl"));
             // let rayshader = device.create_shader_module(wgpu::include_wgsl!("shaders/raytrace.wgsl"));
             pixel_pipeline = RenderPipelineWrapper::new(
                 &device, 
                 &config,
                 &shader,
                 // &rayshader,
-                true,
+                Some(DEPTH_MODE),
                 vec![PipelineResources::Camera , PipelineResources::Material],
                 vec![PipelineBuffers::Model, PipelineBuffers::Instance ],
                 // vec![PipelineBuffers::VertexUV],
@@ -106,7 +167,7 @@ impl Engine
                 &device, 
                 &config,
                 &floorshader,
-                false,
+                None,
                 vec![PipelineResources::Camera],
                 vec![PipelineBuffers::Model],
                 &layouts,
@@ -115,30 +176,70 @@ impl Engine
                 &device, 
                 &config,
                 &finalshader,
-                false,
+                None,
                 vec![PipelineResources::Framebuffer],
                 vec![PipelineBuffers::VertexUV],
                 &layouts,
                 Some("final_pipeline_layout"));
+            skinned_pipeline = RenderPipelineWrapper::new(
+                &device, 
+                &config,
+                &skinnedshader,
+                Some(DEPTH_MODE),
+                vec![PipelineResources::Camera, PipelineResources::Material, PipelineResources::Skin],
+                vec![PipelineBuffers::Skinned, PipelineBuffers::Instance],
+                &layouts,
+                Some("skinned_pipeline_layout"));
+            // gltf meshes come with skinned vertices, the shader only reads the first attributes
+            morph_pipeline = RenderPipelineWrapper::new(
+                &device, 
+                &config,
+                &morphshader,
+                Some(DEPTH_MODE),
+                vec![PipelineResources::Camera, PipelineResources::Material, PipelineResources::Morph],
+                vec![PipelineBuffers::Skinned, PipelineBuffers::Instance],
+                &layouts,
+                Some("morph_pipeline_layout"));
         }
 
 
 
         let world = world::World::new(&device, &queue, &layouts ).await;
+        let overlay = StatsOverlay::new(&device, config.format, STATS_HISTORY);
+        let gpu_timer = GpuTimer::new(&device, &queue, 4);
+        let font = text::load_font("fonts/DejaVuSans.ttf").await.unwrap();
+        let text = TextRenderer::new(&device, config.format, font, TextMode::Sdf);
+        let gui = Gui::new(&device, config.format, &window_state.window);
+        let debug = DebugDraw::new(&device, config.format, &layouts.camera, DEPTH_MODE);
+        let input = Input::new(input::load_input_map("input.ron").await.unwrap());
+        let mut projection = world.camera.projection(config.width, config.height);
+        projection.set_depth_mode(DEPTH_MODE);
         let camera = Camera::new(
-            cgmath::Point3::new(0.0, -10.0, 0.0),
-            cgmath::Deg(0.0),
-            cgmath::Deg(0.0),
-            Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0),
-            CameraController::new(10.0, 0.2),
+            world.camera.position(),
+            world.camera.yaw(),
+            world.camera.pitch(),
+            projection,
+            vec![
+                Box::new(OrbitController::new(cgmath::Point3::new(0.0, 0.0, 0.0), 10.0, 0.2)),
+                Box::new(FpsController::new(10.0, 0.2)),
+                Box::new(ArcballController::new(cgmath::Point3::new(0.0, 0.0, 0.0), 10.0)),
+                Box::new(PanZoomController::new(10.0)),
+            ],
             &device,
             &layouts.camera,
 
         );
 
-        let screenquad_buffer = VertexUV::new_vertex_buffer(&device, &SCREENQUAD);
-        let screenquad_index_buffer = VertexUV::new_index_buffer(&device, &SCREENQUAD_INDICES);
-        let screenquad = Mesh { name: "screenquad".to_string(), vertex_buffer: screenquad_buffer, index_buffer: screenquad_index_buffer, num_elements: 6 };
+        let screenquad = Mesh::new(&device, "screenquad", SCREENQUAD.to_vec(), SCREENQUAD_INDICES.to_vec());
+        let batch_instances = Instances::new(&device, Vec::new());
+        let culler = GpuCuller::new(&device);
+        let cull_targets = world.sphere.meshes.iter().map(|_| culler.target(&device)).collect();
+        let hiz = HiZ::new(&device, &pixelframebuffer.depth_texture.as_ref().unwrap().view,
+            config.width / PIXEL_SIZE, config.height / PIXEL_SIZE, DEPTH_MODE);
+        let skin = Skin::new(&device, &layouts, &world.skeleton);
+        let morph_weights = MorphWeights::new(&device, &layouts.morph,
+            world.blob.meshes[0].morph_targets.as_ref().unwrap(), world.blob_instances.len());
+        let id_buffer = IdBuffer::new(&device, config.width, config.height, &layouts, DEPTH_MODE);
 
         Self
         {
@@ -150,10 +251,39 @@ impl Engine
             pixel_pipeline,
             floor_pipeline,
             final_pipeline,
+            skinned_pipeline,
+            morph_pipeline,
             pixelframebuffer,
             world,
             screenquad,
+            batch_instances,
+            culler,
+            cull_targets,
+            hiz,
+            gpu_culling: false,
+            skin,
+            morph_weights,
+            animation_time: 0.0,
+            recording: None,
+            replay: None,
+            replay_frames: 0,
+            replay_start: instant::Instant::now(),
+            id_buffer,
+            cursor: PhysicalPosition::new(0.0, 0.0),
+            gpu_pick: None,
             mouse_locked: false,
+            input,
+            input_recording: None,
+            input_replay: None,
+            stats: FrameStats::new(STATS_HISTORY),
+            overlay,
+            text,
+            gui,
+            debug,
+            frozen_frustum: None,
+            pixel_size: PIXEL_SIZE,
+            gpu_timer,
+            quit: false,
         }
     }
 
@@ -177,8 +307,8 @@ impl Engine
             self.pixelframebuffer.texture = Some(Texture::create_blank_texture(&self.device, 
                 wgpu::Extent3d 
                 {
-                    width: state.config.width / PIXEL_SIZE,
-                    height: state.config.height / PIXEL_SIZE,
+                    width: state.config.width / self.pixel_size,
+                    height: state.config.height / self.pixel_size,
                     depth_or_array_layers: 1,
                 },
                 "high-res-texture",
@@ -187,108 +317,407 @@ impl Engine
             self.pixelframebuffer.depth_texture = Some(Texture::create_depth_texture(&self.device,
                 wgpu::Extent3d 
                 {
-                    width: self.window_state.config.width / PIXEL_SIZE,
-                    height: self.window_state.config.height / PIXEL_SIZE,
+                    width: self.window_state.config.width / self.pixel_size,
+                    height: self.window_state.config.height / self.pixel_size,
                     depth_or_array_layers: 1,
                 },
                 "depth_texture",
-                wgpu::FilterMode::Nearest));
+                wgpu::FilterMode::Nearest,
+                DEPTH_MODE));
             
             self.pixelframebuffer.bind_group = Some(Framebuffer::make_bind_group(&self.device, &self.layouts, self.pixelframebuffer.texture.as_ref().unwrap(), 
                 self.pixelframebuffer.depth_texture.as_ref().unwrap()));
+            self.hiz.resize(&self.device, &self.pixelframebuffer.depth_texture.as_ref().unwrap().view,
+                new_size.width / self.pixel_size, new_size.height / self.pixel_size);
+            self.id_buffer.resize(&self.device, new_size.width, new_size.height);
         }
     }
 
 
     pub fn window_input(&mut self, event: &WindowEvent) -> bool
     { 
-        match event 
-        {
-            WindowEvent::KeyboardInput {
-                    input: KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Tab),
-                        ..
-                    },
-                    ..
-            } => 
+        // the gui stays out of recorded and replayed sessions, input it swallowed would be missing
+        // from the recording and the replay would drift from what was seen
+        let capturing = self.input_recording.is_none() && self.input_replay.is_none();
+        if capturing && self.gui.window_event(event)
+        {
+            return true;
+        }
+        match InputEvent::from_window(event, self.window_state.size)
+        {
+            // a replayed session ignores the live mouse and keyboard
+            Some(_) if self.input_replay.is_some() => true,
+            Some(event) => self.input_event(&event),
+            None => false,
+        }
+    }
+
+
+    // live and replayed input both end up here, whatever is used gets recorded
+    fn input_event(&mut self, event: &InputEvent) -> bool
+    {
+        let used = match *event
+        {
+            InputEvent::Cursor(x, y) =>
             {
-                if self.mouse_locked == false
+                let size = self.window_state.size;
+                self.cursor = PhysicalPosition::new(x * size.width as f64, y * size.height as f64);
+                self.camera.input.process_mouse_pos(x, y);
+                true
+            }
+            InputEvent::MouseMotion(dx, dy) if self.mouse_locked =>
+            {
+                self.camera.input.process_mouse(dx, dy);
+                true
+            }
+            InputEvent::MouseMotion(..) => false,
+            _ => self.input.handle(event),
+        };
+        if let (true, Some((recording, _))) = (used, &mut self.input_recording)
+        {
+            recording.push_event(*event);
+        }
+        used
+    }
+
+
+    // records every input until finish, to replay the session with --replay-input
+    pub fn record_input(&mut self, file: &str)
+    {
+        log::info!("input: recording to {}", file);
+        self.input_recording = Some((InputRecording::new(), file.to_string()));
+    }
+
+
+    pub fn replay_input(&mut self, file: &str) -> anyhow::Result<()>
+    {
+        let recording = InputRecording::load(file)?;
+        log::info!("input: replaying {} frames, {:.1}s", recording.frames.len(), recording.duration().as_secs_f32());
+        self.input_replay = Some(InputReplay::new(recording));
+        Ok(())
+    }
+
+
+    pub fn finish(&mut self)
+    {
+        if let Some((recording, file)) = self.input_recording.take()
+        {
+            match recording.save(&file)
+            {
+                Ok(()) => log::info!("input: saved {} frames to {}", recording.frames.len(), file),
+                Err(e) => log::warn!("input: {}", e),
+            }
+        }
+    }
+
+
+    // the one shot actions of assets/input.ron, the camera reads movement on its own
+    fn actions(&mut self)
+    {
+        if self.input.pressed("quit")
+        {
+            self.quit = true;
+        }
+        if self.input.pressed("toggle_cursor")
+        {
+            if self.mouse_locked == false
+            {
+                self.window_state.window.set_cursor_grab(CursorGrabMode::Confined).or_else(|_| 
+                    self.window_state.window.set_cursor_grab(CursorGrabMode::Locked)).unwrap();
+                self.window_state.window.set_cursor_visible(false);
+                self.mouse_locked = true; 
+            }
+            else
+            {
+                self.window_state.window.set_cursor_grab(CursorGrabMode::None).unwrap();
+                self.window_state.window.set_cursor_visible(true);
+                self.mouse_locked = false;
+            }
+        }
+        if self.input.pressed("next_controller")
+        {
+            log::info!("camera controller: {}", self.camera.next_controller().name());
+        }
+        if self.input.pressed("flythrough")
+        {
+            if let Some(flythrough) = self.world.animations.iter_mut().find(|a| a.name == "flythrough")
+            {
+                flythrough.clock.restart();
+                log::info!("camera fly-through");
+            }
+        }
+        if self.input.pressed("toggle_stats")
+        {
+            self.overlay.visible = !self.overlay.visible;
+            log::info!("{}", self.stats.summary());
+        }
+        if self.input.pressed("toggle_gui")
+        {
+            self.gui.visible = !self.gui.visible;
+        }
+        if self.input.pressed("toggle_debug")
+        {
+            self.debug.visible = !self.debug.visible;
+        }
+        if self.input.pressed("freeze_frustum")
+        {
+            self.frozen_frustum = match self.frozen_frustum
+            {
+                None => Some(self.camera.view_proj()),
+                Some(_) => None,
+            };
+        }
+        if self.input.pressed("gpu_culling")
+        {
+            self.gpu_culling = !self.gpu_culling;
+            log::info!("gpu culling: {}", self.gpu_culling);
+        }
+        if self.input.pressed("record_path")
+        {
+            match self.recording.take()
+            {
+                None =>
                 {
-                    self.window_state.window.set_cursor_grab(CursorGrabMode::Confined).or_else(|_| 
-                        self.window_state.window.set_cursor_grab(CursorGrabMode::Locked)).unwrap();
-                    self.window_state.window.set_cursor_visible(false);
-                    self.mouse_locked = true; 
+                    self.recording = Some(CameraPath::new());
+                    log::info!("camera path: recording");
                 }
-                else
+                Some(path) => match path.save(CAMERA_PATH_FILE)
                 {
-                    self.window_state.window.set_cursor_grab(CursorGrabMode::None).unwrap();
-                    self.window_state.window.set_cursor_visible(true);
-                    self.mouse_locked = false;
+                    Ok(()) => log::info!("camera path: saved {} frames to {}", path.samples.len(), CAMERA_PATH_FILE),
+                    Err(e) => log::warn!("camera path: {}", e),
                 }
-                true
             }
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        virtual_keycode: Some(key),
-                        state,
-                        ..
-                    },
-                ..
-            } => 
-            { 
-                self.camera.controller.process_keyboard(*key, *state);
-                // self.window.set_title(&format!("{:?}", self.camera.position));
-                true
+        }
+        if self.input.pressed("play_path")
+        {
+            match CameraPath::load(CAMERA_PATH_FILE)
+            {
+                Ok(path) =>
+                {
+                    let path = path.smooth(PATH_SMOOTHING);
+                    let clock = Clock::new(path.duration(), Playback::Once);
+                    log::info!("camera path: playing {:.1}s", path.duration());
+                    self.replay = Some((path, clock));
+                    self.replay_frames = 0;
+                }
+                Err(e) => log::warn!("camera path: {}", e),
             }
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.camera.controller.process_scroll(delta);
-                // self.window.set_title(&format!("{:?}", self.camera.position));
-                true
+        }
+        if self.input.pressed("pick") && !self.mouse_locked
+        {
+            let ray = self.camera.screen_ray(self.cursor, self.window_state.size);
+            match picking::pick(&ray, &[(&self.world.sphere, &self.world.sphere_instances)])
+            {
+                Some(hit) => log::info!("picked sphere instance {} at {:?} ({} away)", hit.instance, hit.point, hit.distance),
+                None => log::info!("picked nothing"),
             }
-            _ => false,
         }
+        if self.input.pressed("gpu_pick") && !self.mouse_locked
+        {
+            self.gpu_pick = Some(self.cursor);
+        }
+        self.camera.input.read_actions(&self.input);
+        self.input.end_frame();
     }
 
 
     pub fn device_input(&mut self, event : &DeviceEvent) -> bool
     { 
-        match event
+        match InputEvent::from_device(event)
         {
-            DeviceEvent::MouseMotion{ delta, } if self.mouse_locked == true => 
-            {
-                self.camera.controller.process_mouse(delta.0, delta.1);
-                true
-            }
-            _ => false,
+            Some(_) if self.input_replay.is_some() => true,
+            Some(event) => self.input_event(&event),
+            None => false,
         }
     }
 
 
     pub fn update(&mut self, dt: instant::Duration, time: instant::Instant)
     { 
-        self.camera.update_orbit(dt);
-        // self.camera.update_fps(dt);
-        self.camera.update_view_proj();
-        self.queue.write_buffer(&self.camera.buffer, 0, bytemuck::cast_slice(&[self.camera.uniform]));
+        // a replayed session brings its own events and frame times
+        let frame = self.input_replay.as_mut().and_then(|replay| replay.next_frame().cloned());
+        if self.input_replay.as_ref().is_some_and(|replay| replay.is_finished()) && frame.is_none()
+        {
+            log::info!("input: replay finished");
+            self.input_replay = None;
+        }
+        let dt = match frame
+        {
+            Some(frame) =>
+            {
+                frame.events.iter().for_each(|event| { self.input_event(event); });
+                frame.dt
+            }
+            None => dt,
+        };
+        if let Some((recording, _)) = &mut self.input_recording
+        {
+            recording.end_frame(dt);
+        }
+
+        // updates run on fixed ticks, so a camera path replays the same way every run and the
+        // frames rendered meanwhile measure the renderer
+        if self.replay.is_some() && self.replay_frames == 0 { self.replay_start = time; }
 
-        // self.world.sphere_instances.instances[0].position = cgmath::Vector3::new(0.0, 0.0, t * 1.0); 
-        // let instance_data = self.world.sphere_instances.instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        // self.queue.write_buffer(&self.world.sphere_instances.buffer, 0, bytemuck::cast_slice(&instance_data));
+        self.actions();
+        self.camera.update(dt);
+        // a running camera animation has the last word on the camera, including its final frame
+        for animation in &mut self.world.animations
+        {
+            let running = animation.clock.playing;
+            animation.clock.advance(dt);
+            if !running { continue; }
+            let time = animation.clock.local_time();
+            match &animation.target
+            {
+                Target::Node(node, a) => a.apply(time, self.world.graph.local_mut(*node)),
+                Target::Camera(a) => a.apply(time, &mut self.camera),
+                Target::Material(material, a) => a.apply(time, &self.queue, &mut self.world.mats[*material]),
+            }
+        }
+        if let Some((path, clock)) = &mut self.replay
+        {
+            path.apply(clock.local_time(), &mut self.camera);
+            clock.advance(dt);
+            if clock.is_finished()
+            {
+                let seconds = (time - self.replay_start).as_secs_f32();
+                log::info!("camera path: {} frames in {:.2}s, {:.2}ms per frame", self.replay_frames, seconds,
+                    1000.0 * seconds / self.replay_frames.max(1) as f32);
+                self.replay = None;
+            }
+        }
+        if let Some(path) = &mut self.recording
+        {
+            path.record(dt, &self.camera.state);
+        }
+        match self.id_buffer.poll(&self.device)
+        {
+            Some(Some((model, instance))) => log::info!("gpu picked model {} instance {}", model, instance),
+            Some(None) => log::info!("gpu picked nothing"),
+            None => {}
+        }
+
+        self.world.graph.update();
+        self.world.graph.upload(&self.device, &self.queue, "sphere", &mut self.world.sphere_instances);
+        self.world.graph.upload(&self.device, &self.queue, "cube", &mut self.world.cube_instances);
+        self.world.graph.upload(&self.device, &self.queue, "plane", &mut self.world.plane_instances);
+        self.debug_lines();
+
+        // tube.gltf has a bend and a sway clip, fade between them
+        self.animation_time += dt.as_secs_f32();
+        let t = self.animation_time;
+        let bend = self.world.clips[0].sample(&self.world.skeleton, t);
+        let sway = self.world.clips[1].sample(&self.world.skeleton, t);
+        let pose = bend.blend(&sway, 0.5 + 0.5 * (t * 0.5).sin());
+        self.skin.update(&self.queue, &self.world.skeleton, &pose);
+
+        // the first blob follows its clip, the second squashes by hand
+        let mut weights = self.morph_weights.get(0).to_vec();
+        self.world.blob_clips[0].sample_weights(t, &mut weights);
+        self.morph_weights.set(0, &weights);
+        self.morph_weights.set(1, &[0.5 + 0.5 * (t * 3.0).sin(), 0.0]);
+        self.morph_weights.upload(&self.queue);
     }
 
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> 
+    // bounds and normals of the spheres, lights and the frozen frustum, rebuilt after every update
+    fn debug_lines(&mut self)
+    {
+        self.debug.clear();
+        if !self.debug.visible
+        {
+            return;
+        }
+        let lines = &mut self.debug;
+        lines.world.grid(cgmath::Point3::new(0.0, 0.0, 0.0), 10.0, 1.0, debug::GRAY);
+        lines.overlay.axes(&cgmath::Matrix4::from_scale(1.0), 1.0);
+        let mesh = &self.world.sphere.meshes[0];
+        for instance in self.world.sphere_instances.iter()
+        {
+            let transform = instance.to_matrix();
+            lines.world.aabb(&mesh.aabb, &transform, debug::YELLOW);
+            lines.world.normals(mesh, &transform, 0.1, debug::BLUE);
+        }
+        for light in &self.world.lights
+        {
+            let color = [light.color[0], light.color[1], light.color[2], 1.0];
+            lines.overlay.point(light.position.into(), 0.5, color);
+            lines.overlay.sphere(light.position.into(), 0.25, color);
+        }
+        if let Some(view_proj) = self.frozen_frustum
+        {
+            lines.overlay.frustum(view_proj, DEPTH_MODE, 20.0, debug::GREEN);
+        }
+    }
+
+
+    // the tuning windows, built once per rendered frame while the gui is visible
+    fn debug_ui(&mut self)
+    {
+        if !self.gui.visible
+        {
+            return;
+        }
+        let ctx = self.gui.begin(&self.window_state.window);
+        let mut pixel_size = self.pixel_size;
+        egui::Window::new("camera").show(&ctx, |ui| gui::camera_panel(ui, &mut self.camera));
+        egui::Window::new("render").show(&ctx, |ui|
+        {
+            ui.add(egui::Slider::new(&mut pixel_size, 1..=8).text("pixel size"));
+            ui.checkbox(&mut self.gpu_culling, "gpu culling");
+            ui.checkbox(&mut self.overlay.visible, "stats");
+            ui.checkbox(&mut self.debug.visible, "debug lines");
+        });
+        egui::Window::new("materials").default_open(false).show(&ctx, |ui| gui::material_panel(ui, &self.queue, &mut self.world.mats));
+        egui::Window::new("scene").default_open(false).show(&ctx, |ui| gui::graph_panel(ui, &mut self.world.graph));
+        self.gui.end(&self.window_state.window);
+        if pixel_size != self.pixel_size
+        {
+            self.pixel_size = pixel_size;
+            self.resize(self.window_state.size);
+        }
+    }
+
+
+    pub fn render(&mut self, alpha: f32) -> Result<(), wgpu::SurfaceError> 
     { 
+        let start = instant::Instant::now();
+        self.gpu_timer.poll(&self.device, &mut self.stats);
+        self.debug_ui();
         let output = self.window_state.surface.get_current_texture()?;
+        // draw the camera alpha of the way between the last two ticks, the simulation keeps its own state
+        let interpolated = self.camera.interpolated(alpha);
+        let state = std::mem::replace(&mut self.camera.state, interpolated);
+        self.camera.update_view_proj();
+        self.queue.write_buffer(&self.camera.buffer, 0, bytemuck::cast_slice(&[self.camera.uniform]));
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut render_queue = RenderQueue::new();
         let mut encoder = self.device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor 
             {
                 label: Some("Render Encoder"),
             }
         );
+        // the gpu path culls every mesh of the model but always draws the full detail level,
+        // level of detail and back to front transparency only happen on the cpu path
+        if self.gpu_culling
+        {
+            let frustum = self.camera.frustum();
+            for (mesh, target) in self.world.sphere.meshes.iter().zip(&mut self.cull_targets)
+            {
+                self.culler.cull(&self.device, &self.queue, &mut encoder, target,
+                    mesh, &self.world.sphere_instances, &frustum, Some(&self.hiz));
+            }
+        }
+        else
+        {
+            render_queue.set_frustum(self.camera.frustum());
+            render_queue.set_lod_view(self.camera.state.position, &self.camera.projection);
+            render_queue.submit_model(&self.pixel_pipeline, &self.world.sphere, &self.world.mats, &self.world.sphere_instances);
+            render_queue.prepare(&self.device, &self.queue, &mut self.batch_instances, self.camera.state.position);
+        }
+        self.debug.prepare(&self.device, &self.queue);
         {
             // let mut render_pass = create_render_pass!(encoder, &self.pixelframebuffer);
             let mut render_pass = encoder.begin_render_pass(
@@ -310,19 +739,48 @@ impl Engine
                     depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                         view: &self.pixelframebuffer.depth_texture.as_ref().unwrap().view,
                         depth_ops: Some(wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(1.0),
+                            load: wgpu::LoadOp::Clear(DEPTH_MODE.clear_value()),
                             store: wgpu::StoreOp::Store,
                         }),
                         stencil_ops: None,
                     }),
-                    timestamp_writes: None,
+                    timestamp_writes: self.gpu_timer.pass("pixel"),
                     occlusion_query_set: None,
                 }
             );
             // render_pass.draw_pipeline_instanced(&self.pixel_pipeline, &self.world.cube, &self.world.mats, &self.world.cube_instances, 0..9, &self.camera.bind_group );
             // render_pass.draw_model_instanced(&self.world.sphere, &self.world.sphere_instances, 0..3);
-            render_pass.set_pipeline_and_bindgroups(&self.pixel_pipeline, &self.world.mats[0],&self.camera.bind_group );
-            render_pass.draw_mesh_instanced( &self.world.sphere.meshes[0], &self.world.sphere_instances, 0..3,  )
+            if self.gpu_culling
+            {
+                let model = &self.world.sphere;
+                for (i, (mesh, target)) in model.meshes.iter().zip(&self.cull_targets).enumerate()
+                {
+                    // meshes past the end of model.materials reuse its last material, like RenderQueue::submit_model
+                    let Some(&material) = model.materials.get(i).or(model.materials.last()) else { continue };
+                    render_pass.set_pipeline_and_bindgroups(&self.pixel_pipeline, &self.world.mats[material as usize], &self.camera.bind_group);
+                    render_pass.draw_mesh_indirect(mesh, &target.instances, &target.indirect);
+                }
+            }
+            else
+            {
+                render_queue.draw(&mut render_pass, &self.batch_instances, &self.camera.bind_group);
+            }
+            let tube = &self.world.tube;
+            render_pass.set_pipeline_and_bindgroups(&self.skinned_pipeline, &self.world.mats[tube.materials[0] as usize], &self.camera.bind_group);
+            render_pass.set_skin(&self.skinned_pipeline, &self.skin);
+            for mesh in &tube.meshes
+            {
+                render_pass.draw_mesh_instanced(mesh, &self.world.tube_instances);
+            }
+            let blob = &self.world.blob;
+            render_pass.set_pipeline_and_bindgroups(&self.morph_pipeline, &self.world.mats[blob.materials[0] as usize], &self.camera.bind_group);
+            render_pass.set_morph(&self.morph_pipeline, &self.morph_weights);
+            render_pass.draw_mesh_instanced(&blob.meshes[0], &self.world.blob_instances);
+            self.debug.render(&mut render_pass, &self.camera.bind_group);
+        }
+        if self.gpu_culling
+        {
+            self.hiz.build(&mut encoder, self.camera.view_proj());
             // render_pass.draw_pipeline_instanced(&self.pixel_pipeline, &self.world.plane, &self.world.plane_instances, 0..1, &self.camera.bind_group );
             // render_pass.set_pipeline(&self.pixel_pipeline.pipeline);
             // render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
@@ -330,7 +788,7 @@ impl Engine
             // render_pass.draw_mesh(&self.screenquad);
         }
         {
-            let mut render_pass = create_render_pass!(encoder, view);
+            let mut render_pass = create_render_pass!(encoder, view, self.gpu_timer.pass("final"));
             render_pass.set_pipeline(&self.floor_pipeline.pipeline);
             render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
             render_pass.draw_mesh(&self.world.floor);
@@ -338,8 +796,42 @@ impl Engine
             render_pass.set_bind_group(0, &self.pixelframebuffer.bind_group.as_ref().unwrap(), &[]);
             render_pass.draw_mesh(&self.screenquad);
         }
+        // the click stays queued until the id buffer is free to take it
+        if let Some(cursor) = self.gpu_pick
+        {
+            if self.id_buffer.render(&mut encoder, &self.queue, &self.camera.bind_group,
+                &[(&self.world.sphere, &self.world.sphere_instances)], cursor.x as u32, cursor.y as u32)
+            {
+                self.gpu_pick = None;
+            }
+        }
+        self.overlay.update(&self.queue, &self.stats, self.window_state.size.width, self.window_state.size.height);
+        self.overlay.render(&mut encoder, &view);
+        if self.overlay.visible
+        {
+            let position = self.camera.state.position;
+            let readout = format!("{:.1} fps  p99 {:.2} ms\ncamera {:.2} {:.2} {:.2}  {}",
+                self.stats.fps(), self.stats.frame.percentile(99.0), position.x, position.y, position.z, self.camera.controller().name());
+            let (width, height) = (self.window_state.size.width, self.window_state.size.height);
+            self.text.queue(&readout, 8.0, 8.0, TEXT_SIZE, Some(width as f32 - 16.0));
+            self.text.prepare(&self.device, &self.queue, width, height);
+            self.text.render(&mut encoder, &view);
+        }
+        self.gui.render(&self.device, &self.queue, &mut encoder, &view);
+        self.gpu_timer.resolve(&mut encoder);
+        self.stats.encode.push(start.elapsed());
+
+        let start = instant::Instant::now();
         self.queue.submit(std::iter::once(encoder.finish()));
+        self.id_buffer.map();
+        self.gpu_timer.map();
+        self.camera.state = state;
         output.present();
+        self.stats.present.push(start.elapsed());
+        if self.replay.is_some()
+        {
+            self.replay_frames += 1;
+        }
 
         Ok(())
     }
@@ -2,7 +2,7 @@ extern crate fstop;
 mod app;
 mod world;
 
-use fstop::engine::WindowState;
+use fstop::engine::{WindowState, FixedTimestep};
 
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
@@ -14,6 +14,11 @@ use winit::
 
 
 
+// simulation ticks per second, and the most ticks one redraw may run to catch up
+const TICK_RATE : u32 = 60;
+const MAX_STEPS : u32 = 5;
+
+
 fn main() 
 {
     pollster::block_on(run());
@@ -31,7 +36,35 @@ async fn run()
     let (event_loop, window) = fstop::window::new("f✦stop");
     let (device, queue, window_state) = fstop::new_device!(window);
     let mut engine = app::Engine::new(window_state, device, queue).await;
+    // --record-input file saves the session on exit, --replay-input file plays one back,
+    // --tick-rate n sets the simulation ticks per second
+    let mut tick_rate = TICK_RATE;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next()
+    {
+        // only known flags take the next argument as their value
+        let known = matches!(arg.as_str(), "--record-input" | "--replay-input" | "--tick-rate");
+        match (arg.as_str(), if known { args.next() } else { None })
+        {
+            (_, None) if known => eprintln!("missing value for {}", arg),
+            ("--record-input", Some(file)) => engine.record_input(&file),
+            ("--replay-input", Some(file)) =>
+            {
+                if let Err(e) = engine.replay_input(&file)
+                {
+                    eprintln!("input: can't replay {}: {}", file, e);
+                }
+            }
+            ("--tick-rate", Some(rate)) => match rate.parse()
+            {
+                Ok(rate) => tick_rate = rate,
+                Err(e) => eprintln!("bad tick rate {}: {}, keeping {}", rate, e, tick_rate),
+            },
+            _ => eprintln!("unknown argument {}", arg),
+        }
+    }
     let mut last_render_time = instant::Instant::now();
+    let mut timestep = FixedTimestep::new(tick_rate, MAX_STEPS);
 
     event_loop.run(
         move |event, _, control_flow| 
@@ -46,17 +79,7 @@ async fn run()
             {
                 match event 
                 {
-                    WindowEvent::CloseRequested | 
-                    WindowEvent::KeyboardInput 
-                    {
-                        input : KeyboardInput 
-                        {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        },
-                        ..
-                    } => *control_flow = ControlFlow::Exit, 
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit, 
 
                     WindowEvent::Resized(physical_size) =>  engine.resize(*physical_size),
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } =>  engine.resize(**new_inner_size),
@@ -72,8 +95,17 @@ async fn run()
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
-                engine.update(dt, last_render_time);
-                match engine.render()
+                for _ in 0..timestep.advance(dt)
+                {
+                    engine.update(timestep.step, last_render_time);
+                }
+                engine.stats.frame.push(dt);
+                engine.stats.update.push(now.elapsed());
+                if engine.quit
+                {
+                    *control_flow = ControlFlow::Exit;
+                }
+                match engine.render(timestep.alpha())
                 {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
@@ -84,6 +116,7 @@ async fn run()
                     Err(e) => eprintln!("{:?}", e),
                 }
             }
+            Event::LoopDestroyed => engine.finish(),
             Event::MainEventsCleared => 
             {
                 // RedrawRequested will only trigger once, unless we manually
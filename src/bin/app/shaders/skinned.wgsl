@@ -0,0 +1,99 @@
+// Vertex shader
+
+
+
+
+struct CameraUniform
+{
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+};
+
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// world transform times inverse bind matrix of every joint
+@group(2) @binding(0)
+var<storage, read> joints: array<mat4x4<f32>>;
+
+
+
+
+struct VertexInput
+{
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+
+struct InstanceInput
+{
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) world_position: vec4<f32>,
+}
+
+
+
+
+@vertex
+fn vs_main( model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let skin = joints[model.joints.x] * model.weights.x
+             + joints[model.joints.y] * model.weights.y
+             + joints[model.joints.z] * model.weights.z
+             + joints[model.joints.w] * model.weights.w;
+    let world = model_matrix * skin;
+
+    var out: VertexOutput;
+    out.uv = model.uv;
+    out.world_position = world * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.proj * camera.view * out.world_position;
+    // fine as long as joints do not scale unevenly
+    out.normal = normalize((world * vec4<f32>(model.normal, 0.0)).xyz);
+    return out;
+}
+
+
+struct Color
+{
+    color: vec4<f32>,
+};
+
+@group(1) @binding(2)
+var<uniform> c_diffuse: Color;
+@group(1) @binding(1)
+var t_diffuse: texture_2d<f32>;
+@group(1)@binding(0)
+var s_diffuse: sampler;
+
+
+
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>
+{
+    let light_direction = vec3<f32>(1.0/sqrt(3.0), 1.0/sqrt(3.0), 1.0/sqrt(3.0));
+    let light_strength = max(dot(normalize(in.normal), light_direction), 0.0);
+    let albedo = textureSample(t_diffuse, s_diffuse, in.uv) * c_diffuse.color;
+    // a little ambient so faces turned away from the light keep their color
+    return vec4<f32>(albedo.rgb * (0.2 + 0.8 * light_strength), albedo.a);
+}
@@ -0,0 +1,532 @@
+use crate::core::model::{Color, Instance, Material};
+use crate::core::camera::{Camera, look_rotation};
+use crate::core::graph::NodeId;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Rad, Vector3};
+use instant::Duration;
+use serde::Deserialize;
+
+
+
+
+// EASING {{{
+// shapes the way from one keyframe to the next, t is in 0..=1 at both ends
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Easing
+{
+    // holds the value until the next keyframe
+    Step,
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // css style cubic bezier through (0, 0), (x1, y1), (x2, y2) and (1, 1)
+    Bezier(f32, f32, f32, f32),
+}
+
+
+impl Easing
+{
+    pub fn apply(&self, t: f32) -> f32
+    {
+        let t = t.clamp(0.0, 1.0);
+        match *self
+        {
+            Easing::Step => 0.0,
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Bezier(x1, y1, x2, y2) =>
+            {
+                let bezier = |a: f32, b: f32, s: f32| 3.0 * a * s * (1.0 - s).powi(2) + 3.0 * b * s * s * (1.0 - s) + s * s * s;
+                // x is monotonic for x1, x2 in 0..=1, a few newton steps find the s that gives t
+                let mut s = t;
+                for _ in 0..8
+                {
+                    let slope = 3.0 * x1 * (1.0 - s).powi(2) + 6.0 * (x2 - x1) * s * (1.0 - s) + 3.0 * (1.0 - x2) * s * s;
+                    if slope.abs() < 1e-6 { break; }
+                    s = (s - (bezier(x1, x2, s) - t) / slope).clamp(0.0, 1.0);
+                }
+                bezier(y1, y2, s)
+            }
+        }
+    }
+}
+// }}}
+
+
+
+
+// VALUES {{{
+pub trait Animatable: Copy
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self;
+}
+
+
+impl Animatable for f32
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self { a + (b - a) * t }
+}
+
+
+impl Animatable for Rad<f32>
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self { a + (b - a) * t }
+}
+
+
+impl Animatable for Vector3<f32>
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self { a.lerp(b, t) }
+}
+
+
+impl Animatable for Point3<f32>
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self { a + (b - a) * t }
+}
+
+
+// q and -q are the same rotation, take the short way round
+impl Animatable for Quaternion<f32>
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self
+    {
+        let b = if a.dot(b) < 0.0 { -b } else { b };
+        a.slerp(b, t)
+    }
+}
+
+
+impl Animatable for Color
+{
+    fn interpolate(a: Self, b: Self, t: f32) -> Self
+    {
+        Color { color: std::array::from_fn(|i| a.color[i] + (b.color[i] - a.color[i]) * t) }
+    }
+}
+// }}}
+
+
+
+
+// TRACKS {{{
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T>
+{
+    pub time: f32,
+    pub value: T,
+    // how the value moves on towards the next keyframe
+    pub easing: Easing,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Track<T>
+{
+    keys: Vec<Keyframe<T>>,
+}
+
+
+impl<T: Animatable> Track<T>
+{
+    pub fn new() -> Self
+    {
+        Self { keys: Vec::new() }
+    }
+
+    pub fn key(mut self, time: f32, value: T, easing: Easing) -> Self
+    {
+        self.insert(time, value, easing);
+        self
+    }
+
+    // keeps keyframes ordered by time, a keyframe at an existing time replaces it
+    pub fn insert(&mut self, time: f32, value: T, easing: Easing)
+    {
+        let key = Keyframe { time, value, easing };
+        match self.keys.binary_search_by(|k| k.time.total_cmp(&time))
+        {
+            Ok(i) => self.keys[i] = key,
+            Err(i) => self.keys.insert(i, key),
+        }
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] { &self.keys }
+    pub fn is_empty(&self) -> bool { self.keys.is_empty() }
+    pub fn duration(&self) -> f32 { self.keys.last().map_or(0.0, |k| k.time) }
+
+    // holds the first and last value outside the keyed range
+    pub fn sample(&self, time: f32) -> Option<T>
+    {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time { return Some(first.value); }
+        if time >= last.time { return Some(last.value); }
+
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let (a, b) = (&self.keys[i], &self.keys[i + 1]);
+        let t = (time - a.time) / (b.time - a.time);
+        Some(T::interpolate(a.value, b.value, a.easing.apply(t)))
+    }
+}
+
+
+impl<T: Animatable> Default for Track<T>
+{
+    fn default() -> Self { Self::new() }
+}
+// }}}
+
+
+
+
+// PLAYBACK {{{
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Playback
+{
+    Once,
+    Loop,
+    // forwards then backwards
+    PingPong,
+}
+
+
+// time of one running animation, advanced by the frame's dt
+#[derive(Debug, Clone)]
+pub struct Clock
+{
+    pub time: f32,
+    pub duration: f32,
+    pub speed: f32,
+    pub playback: Playback,
+    pub playing: bool,
+}
+
+
+impl Clock
+{
+    pub fn new(duration: f32, playback: Playback) -> Self
+    {
+        Self { time: 0.0, duration, speed: 1.0, playback, playing: true }
+    }
+
+    pub fn advance(&mut self, dt: Duration)
+    {
+        if !self.playing { return; }
+        self.time += dt.as_secs_f32() * self.speed;
+        if self.playback == Playback::Once && self.time >= self.duration
+        {
+            self.time = self.duration;
+            self.playing = false;
+        }
+    }
+
+    pub fn restart(&mut self)
+    {
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn is_finished(&self) -> bool
+    {
+        self.playback == Playback::Once && self.time >= self.duration
+    }
+
+    // the time to sample tracks at
+    pub fn local_time(&self) -> f32
+    {
+        if self.duration <= 0.0 { return 0.0; }
+        match self.playback
+        {
+            Playback::Once => self.time.clamp(0.0, self.duration),
+            Playback::Loop => self.time.rem_euclid(self.duration),
+            Playback::PingPong =>
+            {
+                let t = self.time.rem_euclid(2.0 * self.duration);
+                if t > self.duration { 2.0 * self.duration - t } else { t }
+            }
+        }
+    }
+}
+// }}}
+
+
+
+
+// TARGETS {{{
+// empty tracks leave their part of the instance alone
+#[derive(Debug, Clone, Default)]
+pub struct TransformAnimation
+{
+    pub position: Track<Vector3<f32>>,
+    pub rotation: Track<Quaternion<f32>>,
+    pub scale: Track<Vector3<f32>>,
+}
+
+
+impl TransformAnimation
+{
+    pub fn duration(&self) -> f32
+    {
+        self.position.duration().max(self.rotation.duration()).max(self.scale.duration())
+    }
+
+    pub fn apply(&self, time: f32, instance: &mut Instance)
+    {
+        if let Some(p) = self.position.sample(time) { instance.position = p; }
+        if let Some(r) = self.rotation.sample(time) { instance.rotation = r; }
+        if let Some(s) = self.scale.sample(time) { instance.scale = s; }
+    }
+}
+
+
+// scripted camera moves, overrides whatever the active controller did this frame
+#[derive(Debug, Clone, Default)]
+pub struct CameraAnimation
+{
+    pub position: Track<Point3<f32>>,
+    pub rotation: Track<Quaternion<f32>>,
+    pub fovy: Track<Rad<f32>>,
+}
+
+
+impl CameraAnimation
+{
+    // keys the position and a rotation looking from eye to target at the same time
+    pub fn look_at(mut self, time: f32, eye: Point3<f32>, target: Point3<f32>, easing: Easing) -> Self
+    {
+        self.position.insert(time, eye, easing);
+        self.rotation.insert(time, look_rotation(eye, target), easing);
+        self
+    }
+
+    pub fn duration(&self) -> f32
+    {
+        self.position.duration().max(self.rotation.duration()).max(self.fovy.duration())
+    }
+
+    pub fn apply(&self, time: f32, camera: &mut Camera)
+    {
+        if let Some(p) = self.position.sample(time) { camera.state.position = p; }
+        if let Some(r) = self.rotation.sample(time) { camera.state.rotation = r; }
+        if let Some(f) = self.fovy.sample(time) { camera.projection.set_fovy(f); }
+        camera.state.velocity = Vector3::zero();
+    }
+}
+
+
+// a single float of a material's uniform, for fading or tinting without keying whole colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum MaterialFloat
+{
+    Red,
+    Green,
+    Blue,
+    Opacity,
+}
+
+
+// writes the sampled color into the material's uniform, empty tracks leave it alone
+#[derive(Debug, Clone, Default)]
+pub struct MaterialAnimation
+{
+    pub color: Track<Color>,
+    // sampled after color, a float track overrides its channel of it
+    pub floats: Vec<(MaterialFloat, Track<f32>)>,
+}
+
+
+impl MaterialAnimation
+{
+    pub fn duration(&self) -> f32
+    {
+        self.floats.iter().map(|(_, track)| track.duration()).fold(self.color.duration(), f32::max)
+    }
+
+    // the color at time, starting from current for the channels no track drives
+    pub fn sample(&self, time: f32, current: Color) -> Option<Color>
+    {
+        let mut color = self.color.sample(time);
+        for (float, track) in &self.floats
+        {
+            if let Some(value) = track.sample(time)
+            {
+                color.get_or_insert(current).color[*float as usize] = value;
+            }
+        }
+        color
+    }
+
+    pub fn apply(&self, time: f32, queue: &wgpu::Queue, material: &mut Material)
+    {
+        if let Some(c) = self.sample(time, material.diffuse_color) { material.set_color(queue, c); }
+    }
+}
+// }}}
+
+
+
+
+// ANIMATIONS {{{
+// what a scene animation drives
+#[derive(Debug, Clone)]
+pub enum Target
+{
+    Node(NodeId, TransformAnimation),
+    Camera(CameraAnimation),
+    // index into the scene's materials
+    Material(usize, MaterialAnimation),
+}
+
+
+impl Target
+{
+    pub fn duration(&self) -> f32
+    {
+        match self
+        {
+            Target::Node(_, a) => a.duration(),
+            Target::Camera(a) => a.duration(),
+            Target::Material(_, a) => a.duration(),
+        }
+    }
+}
+
+
+// an animation declared in a scene file, playing on its own clock
+#[derive(Debug, Clone)]
+pub struct Animation
+{
+    pub name: String,
+    pub target: Target,
+    pub clock: Clock,
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool { (a - b).abs() < 1e-4 }
+
+    #[test]
+    fn easing_keeps_the_ends_and_shapes_the_middle()
+    {
+        let easings = [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut, Easing::Bezier(0.25, 0.1, 0.25, 1.0)];
+        for easing in easings
+        {
+            assert!(close(easing.apply(0.0), 0.0) && close(easing.apply(1.0), 1.0), "{:?}", easing);
+            assert!(close(easing.apply(-1.0), 0.0) && close(easing.apply(2.0), 1.0), "{:?} is not clamped", easing);
+        }
+        assert_eq!(Easing::Step.apply(0.99), 0.0);
+        assert!(close(Easing::Linear.apply(0.25), 0.25));
+        assert!(close(Easing::EaseIn.apply(0.5), 0.125));
+        assert!(close(Easing::EaseOut.apply(0.5), 0.875));
+        assert!(close(Easing::EaseInOut.apply(0.5), 0.5));
+        // control points on the diagonal make the curve a straight line
+        assert!(close(Easing::Bezier(0.25, 0.25, 0.75, 0.75).apply(0.3), 0.3));
+    }
+
+    #[test]
+    fn bezier_newton_solve_lands_on_x()
+    {
+        let (x1, y1, x2, y2) = (0.42, 0.0, 0.58, 1.0);
+        let bezier = |a: f32, b: f32, s: f32| 3.0 * a * s * (1.0 - s).powi(2) + 3.0 * b * s * s * (1.0 - s) + s * s * s;
+        for i in 0..=20
+        {
+            // walk the curve by its parameter and check the easing finds the same point from x
+            let s = i as f32 / 20.0;
+            let (x, y) = (bezier(x1, x2, s), bezier(y1, y2, s));
+            assert!(close(Easing::Bezier(x1, y1, x2, y2).apply(x), y), "s {}: {} != {}", s, Easing::Bezier(x1, y1, x2, y2).apply(x), y);
+        }
+    }
+
+    #[test]
+    fn track_insert_orders_and_replaces()
+    {
+        let mut track = Track::new();
+        track.insert(2.0, 20.0, Easing::Linear);
+        track.insert(0.0, 0.0, Easing::Linear);
+        track.insert(1.0, 10.0, Easing::Linear);
+        track.insert(1.0, 5.0, Easing::Step);
+        let times: Vec<f32> = track.keys().iter().map(|k| k.time).collect();
+        assert_eq!(times, [0.0, 1.0, 2.0]);
+        assert_eq!(track.keys()[1].value, 5.0);
+        assert_eq!(track.keys()[1].easing, Easing::Step);
+        assert_eq!(track.duration(), 2.0);
+    }
+
+    #[test]
+    fn track_sample_eases_between_keys_and_holds_outside()
+    {
+        assert_eq!(Track::<f32>::new().sample(1.0), None);
+        let track = Track::new()
+            .key(1.0, 10.0, Easing::Linear)
+            .key(2.0, 20.0, Easing::Step)
+            .key(3.0, 40.0, Easing::Linear);
+        assert_eq!(track.sample(0.0), Some(10.0));
+        assert_eq!(track.sample(5.0), Some(40.0));
+        assert!(close(track.sample(1.5).unwrap(), 15.0));
+        // the easing of the key before decides
+        assert_eq!(track.sample(2.9), Some(20.0));
+        assert_eq!(track.sample(3.0), Some(40.0));
+
+        let colors = Track::new()
+            .key(0.0, Color { color: [0.0, 0.0, 0.0, 1.0] }, Easing::Linear)
+            .key(1.0, Color { color: [1.0, 0.5, 0.0, 1.0] }, Easing::Linear);
+        assert_eq!(colors.sample(0.5).unwrap().color, [0.5, 0.25, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn clock_local_time_loops_and_ping_pongs()
+    {
+        let mut looping = Clock::new(2.0, Playback::Loop);
+        let mut ping_pong = Clock::new(2.0, Playback::PingPong);
+        for (time, looped, ponged) in [(0.5, 0.5, 0.5), (2.5, 0.5, 1.5), (3.5, 1.5, 0.5), (4.5, 0.5, 0.5)]
+        {
+            for clock in [&mut looping, &mut ping_pong]
+            {
+                clock.time = 0.0;
+                clock.advance(Duration::from_secs_f32(time));
+            }
+            assert!(close(looping.local_time(), looped), "loop at {}: {}", time, looping.local_time());
+            assert!(close(ping_pong.local_time(), ponged), "ping pong at {}: {}", time, ping_pong.local_time());
+        }
+        let mut once = Clock::new(2.0, Playback::Once);
+        once.advance(Duration::from_secs_f32(2.5));
+        assert!(once.is_finished() && !once.playing);
+        assert_eq!(once.local_time(), 2.0);
+        assert_eq!(Clock::new(0.0, Playback::Loop).local_time(), 0.0);
+    }
+
+    #[test]
+    fn material_floats_drive_single_channels()
+    {
+        let base = Color { color: [0.2, 0.4, 0.6, 1.0] };
+        let fade = MaterialAnimation
+        {
+            floats: vec![(MaterialFloat::Opacity, Track::new().key(0.0, 1.0, Easing::Linear).key(2.0, 0.0, Easing::Linear))],
+            ..Default::default()
+        };
+        assert_eq!(fade.duration(), 2.0);
+        assert_eq!(fade.sample(1.0, base).unwrap().color, [0.2, 0.4, 0.6, 0.5]);
+        assert!(MaterialAnimation::default().sample(1.0, base).is_none());
+
+        // the float wins over the color track on its channel
+        let tint = MaterialAnimation
+        {
+            color: Track::new().key(0.0, Color { color: [1.0; 4] }, Easing::Linear),
+            floats: vec![(MaterialFloat::Green, Track::new().key(3.0, 0.25, Easing::Linear))],
+        };
+        assert_eq!(tint.duration(), 3.0);
+        assert_eq!(tint.sample(0.0, base).unwrap().color, [1.0, 0.25, 1.0, 1.0]);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::core::model;
 use crate::core::renderer;
 use crate::core::texture;
+use crate::core::bounds::{Aabb, BoundingSphere};
+use crate::core::skeleton::{Skeleton, Clip};
 
 
 use std::io::{BufReader, Cursor};
@@ -81,6 +83,36 @@ pub async fn load_texture(
 
 
 
+pub async fn load_materials(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,) -> anyhow::Result<Vec<model::Material>> 
+{
+    let mtl_text = load_string(file_name).await?;
+    let (obj_materials, _) = tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mtl_text)))?;
+    create_materials(obj_materials, device, queue, layout).await
+}
+
+
+async fn create_materials(
+    obj_materials: Vec<tobj::Material>,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,) -> anyhow::Result<Vec<model::Material>> 
+{
+    let mut materials = Vec::new();
+    for m in obj_materials {
+        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
+        let diffuse_color = model::Color { color: [m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0] };
+        materials.push(model::Material::new(device, layout, &m.name, diffuse_texture, diffuse_color));
+    }
+    Ok(materials)
+}
+
+
+
+
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
@@ -104,59 +136,21 @@ pub async fn load_model(
         },
     )
     .await?;
-    let mut materials = Vec::new();
-    
-    if obj_materials.is_ok() {
-        for m in obj_materials? {
-            let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-            let diffuse_color = model::Color { color: [m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0] };
-            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Buffer(
-                            wgpu::BufferBinding {
-                                buffer: &device.create_buffer_init(
-                                    &wgpu::util::BufferInitDescriptor {
-                                        label: Some("Material Color Buffer"),
-                                        contents: bytemuck::cast_slice(&[diffuse_color]),
-                                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                                    }
-                                ),
-                                offset: 0,
-                                size: None,
-                            }
-                        ),
-                    },
-                ],
-                label: None,
-            });
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            diffuse_color: model::Color {color : [m.diffuse[0], m.diffuse[1], m.diffuse[2], 1.0]},
-            bind_group,
-        });
-    }}
+    let materials = match obj_materials {
+        Ok(obj_materials) => create_materials(obj_materials, device, queue, layout).await?,
+        Err(_) => Vec::new(),
+    };
     let meshes = models
         .into_iter()
         .map(|m| {
-            let pos = (0..m.mesh.positions.len() / 3)
+            let pos : Vec<[f32; 3]> = (0..m.mesh.positions.len() / 3)
                 .map(|i| [
                         m.mesh.positions[i * 3],
                         m.mesh.positions[i * 3 + 1],
                         m.mesh.positions[i * 3 + 2],
                     ]
-                );
+                )
+                .collect();
 
             let uv : Vec<[f32; 2]> = if m.mesh.texcoords.len() > 0 {
                 (0..m.mesh.texcoords.len() / 2)
@@ -182,7 +176,7 @@ pub async fn load_model(
                     .collect()
             };
 
-            let vertices = pos.zip(uv).zip(normals).map(|((pos, uv), normal)| renderer::ModelVertex {
+            let vertices = pos.iter().copied().zip(uv).zip(normals.iter().copied()).map(|((pos, uv), normal)| renderer::ModelVertex {
                 position: pos,
                 uv,
                 normal,
@@ -201,14 +195,97 @@ pub async fn load_model(
                 usage: wgpu::BufferUsages::INDEX,
             });
 
+            let aabb = Aabb::from_points(&pos);
+            let bounding_sphere = BoundingSphere::from_points(&pos, &aabb);
+
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
+                positions: pos,
+                normals,
+                indices: m.mesh.indices,
+                aabb,
+                bounding_sphere,
+                lods: Vec::new(),
+                morph_targets: None,
             }
         })
         .collect::<Vec<_>>();
     Ok((meshes, materials))
 }
 
+
+
+
+// meshes of a gltf or glb file with SkinnedVertex vertices and their morph targets, the first
+// skin and every animation. Without a skin the skeleton is empty. External buffers are loaded
+// next to the file, embedded data uris are not supported.
+pub async fn load_gltf(
+    file_name: &str,
+    device: &wgpu::Device,) -> anyhow::Result<(Vec<model::Mesh>, Skeleton, Vec<Clip>)>
+{
+    let data = load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&data)?;
+
+    let mut buffers = Vec::new();
+    for buffer in gltf.buffers() {
+        match buffer.source() {
+            gltf::buffer::Source::Bin => buffers.push(
+                gltf.blob.clone().ok_or_else(|| anyhow::anyhow!("{}: missing binary chunk", file_name))?
+            ),
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") =>
+                anyhow::bail!("{}: embedded buffers are not supported", file_name),
+            gltf::buffer::Source::Uri(uri) => buffers.push(load_binary(uri).await?),
+        }
+    }
+
+    let skin = gltf.skins().next();
+    let skeleton = skin.as_ref()
+        .map_or_else(|| Skeleton::new(Vec::new()), |skin| Skeleton::from_gltf(skin, &buffers));
+    let clips = gltf.animations().map(|a| Clip::from_gltf(&a, skin.as_ref(), &buffers)).collect();
+
+    let mut meshes = Vec::new();
+    for mesh in gltf.meshes() {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let Some(positions) = reader.read_positions() else { continue };
+            let positions: Vec<[f32; 3]> = positions.collect();
+            let count = positions.len();
+            let normals: Vec<[f32; 3]> = reader.read_normals()
+                .map_or_else(|| vec![[0.0; 3]; count], Iterator::collect);
+            let uvs: Vec<[f32; 2]> = reader.read_tex_coords(0)
+                .map_or_else(|| vec![[0.0; 2]; count], |t| t.into_f32().collect());
+            let joints: Vec<[u32; 4]> = reader.read_joints(0)
+                .map_or_else(|| vec![[0; 4]; count], |j| j.into_u16().map(|j| j.map(u32::from)).collect());
+            let weights: Vec<[f32; 4]> = reader.read_weights(0)
+                .map_or_else(|| vec![[1.0, 0.0, 0.0, 0.0]; count], |w| w.into_f32().collect());
+            let indices: Vec<u32> = reader.read_indices()
+                .map_or_else(|| (0..count as u32).collect(), |i| i.into_u32().collect());
+
+            let vertices = (0..count).map(|i| renderer::SkinnedVertex {
+                position: positions[i],
+                uv: uvs[i],
+                normal: normals[i],
+                joints: joints[i],
+                weights: weights[i],
+            }).collect::<Vec<_>>();
+            let targets: Vec<model::MorphTarget> = reader.read_morph_targets()
+                .map(|(positions, normals, _)| model::MorphTarget {
+                    positions: positions.map_or_else(Vec::new, Iterator::collect),
+                    normals: normals.map_or_else(Vec::new, Iterator::collect),
+                })
+                .collect();
+
+            let name = mesh.name().unwrap_or(file_name);
+            let mut m = model::Mesh::new(device, name, vertices, indices);
+            if !targets.is_empty() {
+                let weights = mesh.weights().unwrap_or(&[]);
+                m.morph_targets = Some(model::MorphTargets::new(device, &targets, count, weights));
+            }
+            meshes.push(m);
+        }
+    }
+    Ok((meshes, skeleton, clips))
+}
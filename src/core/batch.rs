@@ -0,0 +1,328 @@
+use crate::core::model::{Mesh, Material, Model, Instance, Instances};
+use crate::core::renderer::{Draw, RenderPipelineWrapper, PipelineResources};
+use crate::core::bounds::Frustum;
+use crate::core::camera::Projection;
+
+use std::ops::Range;
+use cgmath::{InnerSpace, Point3};
+
+
+
+
+// ITEMS {{{
+#[derive(Clone, Copy)]
+pub struct RenderItem<'a>
+{
+    pub pipeline: &'a RenderPipelineWrapper,
+    pub mesh: &'a Mesh,
+    pub material: &'a Material,
+    pub transform: Instance,
+    // index into Mesh::lod
+    pub lod: usize,
+    // world space center of the mesh bounds, transparent items are sorted on it
+    pub center: Point3<f32>,
+}
+
+
+impl RenderItem<'_>
+{
+    // pointers are the identity of gpu state, two items with equal keys draw together
+    fn key(&self) -> SortKey
+    {
+        (
+            self.pipeline as *const _ as usize,
+            self.material as *const _ as usize,
+            self.mesh as *const _ as usize,
+            self.lod,
+        )
+    }
+}
+
+
+// pipeline, material, mesh, lod
+type SortKey = (usize, usize, usize, usize);
+
+
+// items are (key, transparent, squared distance from the eye), returns the draw order as indices into items
+// and the runs of that order that share a key, each run becomes one batch
+// opaque items come first grouped by key, transparent ones after them from back to front
+fn plan(items: &[(SortKey, bool, f32)]) -> (Vec<usize>, Vec<Range<usize>>)
+{
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by(|&a, &b|
+    {
+        let ((a_key, a_transparent, a_distance), (b_key, b_transparent, b_distance)) = (items[a], items[b]);
+        match (a_transparent, b_transparent)
+        {
+            (false, false) => a_key.cmp(&b_key),
+            (true, true) => b_distance.total_cmp(&a_distance),
+            _ => a_transparent.cmp(&b_transparent),
+        }
+    });
+
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, &item) in order.iter().enumerate()
+    {
+        let (key, transparent, _) = items[item];
+        match runs.last_mut()
+        {
+            Some(run) if items[order[run.start]].0 == key && items[order[run.start]].1 == transparent => run.end = i + 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    (order, runs)
+}
+
+
+// one instanced draw call, instances index into the queue's instance buffer
+pub struct Batch<'a>
+{
+    pub pipeline: &'a RenderPipelineWrapper,
+    pub mesh: &'a Mesh,
+    pub material: &'a Material,
+    pub lod: usize,
+    // drawn with the pipeline's transparent twin, blending without writing depth
+    pub transparent: bool,
+    pub instances: Range<u32>,
+}
+// }}}
+
+
+
+
+// QUEUE {{{
+pub struct RenderQueue<'a>
+{
+    // items and whether their material is transparent
+    items: Vec<(RenderItem<'a>, bool)>,
+    batches: Vec<Batch<'a>>,
+    frustum: Option<Frustum>,
+    lod_view: Option<(Point3<f32>, Projection)>,
+    culled: usize,
+}
+
+
+impl<'a> RenderQueue<'a>
+{
+    pub fn new() -> Self
+    {
+        Self
+        {
+            items: Vec::new(),
+            batches: Vec::new(),
+            frustum: None,
+            lod_view: None,
+            culled: 0,
+        }
+    }
+
+
+    // items submitted afterwards are dropped when their mesh bounds are outside the frustum
+    pub fn set_frustum(&mut self, frustum: Frustum)
+    {
+        self.frustum = Some(frustum);
+    }
+
+
+    // items submitted afterwards draw the coarsest level of their mesh that looks right from eye
+    pub fn set_lod_view(&mut self, eye: Point3<f32>, projection: &Projection)
+    {
+        self.lod_view = Some((eye, projection.clone()));
+    }
+
+
+    // items dropped by the frustum since the queue was created
+    pub fn culled(&self) -> usize { self.culled }
+
+
+    pub fn submit(&mut self, pipeline: &'a RenderPipelineWrapper, mesh: &'a Mesh, material: &'a Material, transform: Instance)
+    {
+        let sphere = mesh.bounding_sphere.transform(&transform.to_matrix());
+        if let Some(frustum) = &self.frustum
+        {
+            if !frustum.intersects_sphere(&sphere)
+            {
+                self.culled += 1;
+                return;
+            }
+        }
+        let lod = match &self.lod_view
+        {
+            Some((eye, projection)) if !mesh.lods.is_empty() =>
+            {
+                // errors are in object space, the nearest point of the bounds is the worst case
+                let distance = (sphere.center - eye).magnitude() - sphere.radius;
+                let scale = transform.scale.x.abs().max(transform.scale.y.abs()).max(transform.scale.z.abs());
+                mesh.select_lod(projection.pixels_per_unit(distance) * scale)
+            }
+            _ => 0,
+        };
+        let item = RenderItem { pipeline, mesh, material, transform, lod, center: sphere.center };
+        self.items.push((item, material.is_transparent()));
+    }
+
+
+    pub fn submit_instances(&mut self, pipeline: &'a RenderPipelineWrapper, mesh: &'a Mesh, material: &'a Material, instances: &Instances)
+    {
+        for instance in instances.iter()
+        {
+            self.submit(pipeline, mesh, material, *instance);
+        }
+    }
+
+
+    // meshes past the end of model.materials reuse its last material
+    pub fn submit_model(&mut self, pipeline: &'a RenderPipelineWrapper, model: &'a Model, materials: &'a [Material], instances: &Instances)
+    {
+        for (i, mesh) in model.meshes.iter().enumerate()
+        {
+            let Some(&material) = model.materials.get(i).or(model.materials.last()) else { continue };
+            self.submit_instances(pipeline, mesh, &materials[material as usize], instances);
+        }
+    }
+
+
+    // sorts and groups the submitted items and writes their transforms into instances,
+    // opaque items are grouped by pipeline, material and mesh to keep state changes low,
+    // transparent ones are drawn after them from back to front as seen from eye, on the pipelines' transparent twins
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &mut Instances, eye: Point3<f32>)
+    {
+        let keys: Vec<_> = self.items
+            .iter()
+            .map(|(item, transparent)| (item.key(), *transparent, (item.center - eye).magnitude2()))
+            .collect();
+        let (order, runs) = plan(&keys);
+
+        self.batches.clear();
+        instances.clear();
+        for run in runs
+        {
+            let (item, transparent) = self.items[order[run.start]];
+            let start = instances.len() as u32;
+            for &i in &order[run]
+            {
+                instances.push(self.items[i].0.transform);
+            }
+            self.batches.push(Batch
+            {
+                pipeline: item.pipeline,
+                mesh: item.mesh,
+                material: item.material,
+                lod: item.lod,
+                transparent,
+                instances: start..instances.len() as u32,
+            });
+        }
+        self.items.clear();
+        instances.upload(device, queue);
+    }
+
+
+    pub fn batches(&self) -> &[Batch<'a>] { &self.batches }
+
+
+    // instances must be the buffer passed to prepare
+    pub fn draw<'p>(&self, render_pass: &mut wgpu::RenderPass<'p>, instances: &'p Instances, camera: &'p wgpu::BindGroup)
+    where
+        'a: 'p,
+    {
+        let mut pipeline : Option<(&RenderPipelineWrapper, bool)> = None;
+        let mut material : Option<&Material> = None;
+        let mut mesh : Option<(&Mesh, usize)> = None;
+        render_pass.set_vertex_buffer(1, instances.buffer.slice(..));
+
+        for batch in &self.batches
+        {
+            if !pipeline.is_some_and(|(p, transparent)| std::ptr::eq(p, batch.pipeline) && transparent == batch.transparent)
+            {
+                render_pass.set_pipeline_and_bindgroups(batch.pipeline, batch.material, camera);
+                // the twin shares the layout, the bind groups stay valid
+                if let (true, Some(transparent)) = (batch.transparent, &batch.pipeline.transparent)
+                {
+                    render_pass.set_pipeline(transparent);
+                }
+                pipeline = Some((batch.pipeline, batch.transparent));
+                material = Some(batch.material);
+            }
+            else if !material.is_some_and(|m| std::ptr::eq(m, batch.material))
+            {
+                if let Some(i) = batch.pipeline.resources.iter().position(|r| matches!(r, PipelineResources::Material))
+                {
+                    render_pass.set_bind_group(i as u32, &batch.material.bind_group, &[]);
+                }
+                material = Some(batch.material);
+            }
+            let (index_buffer, num_elements) = batch.mesh.lod(batch.lod);
+            let same_mesh = mesh.is_some_and(|(m, _)| std::ptr::eq(m, batch.mesh));
+            if !same_mesh
+            {
+                render_pass.set_vertex_buffer(0, batch.mesh.vertex_buffer.slice(..));
+            }
+            if !same_mesh || mesh.is_some_and(|(_, lod)| lod != batch.lod)
+            {
+                render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            }
+            mesh = Some((batch.mesh, batch.lod));
+            render_pass.draw_indexed(0..num_elements, 0, batch.instances.clone());
+        }
+    }
+}
+
+
+impl Default for RenderQueue<'_>
+{
+    fn default() -> Self { Self::new() }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // (pipeline, material, mesh, lod) as small numbers instead of pointers
+    fn opaque(key: SortKey) -> (SortKey, bool, f32) { (key, false, 0.0) }
+    fn transparent(key: SortKey, distance: f32) -> (SortKey, bool, f32) { (key, true, distance * distance) }
+
+    fn batches(items: &[(SortKey, bool, f32)]) -> Vec<Vec<usize>>
+    {
+        let (order, runs) = plan(items);
+        runs.into_iter().map(|run| order[run].to_vec()).collect()
+    }
+
+    #[test]
+    fn opaque_items_group_by_pipeline_material_mesh_and_lod()
+    {
+        let items = [
+            opaque((1, 1, 1, 0)),
+            opaque((0, 1, 1, 0)),
+            opaque((1, 1, 1, 1)),
+            opaque((1, 0, 1, 0)),
+            opaque((1, 1, 1, 0)),
+            opaque((1, 1, 0, 0)),
+            opaque((0, 1, 1, 0)),
+        ];
+        // pipelines change least often, then materials, meshes and lods
+        assert_eq!(batches(&items), vec![vec![1, 6], vec![3], vec![5], vec![0, 4], vec![2]]);
+        assert!(batches(&[]).is_empty());
+    }
+
+    #[test]
+    fn transparent_items_draw_back_to_front_after_opaque()
+    {
+        let items = [
+            transparent((0, 0, 0, 0), 2.0),
+            opaque((0, 0, 0, 0)),
+            transparent((0, 0, 0, 0), 8.0),
+            transparent((1, 0, 0, 0), 5.0),
+            transparent((0, 0, 0, 0), 6.0),
+            opaque((1, 0, 0, 0)),
+        ];
+        // an opaque and a transparent item never share a batch even with equal keys,
+        // and transparent ones only merge while nothing else lies between them
+        assert_eq!(batches(&items), vec![vec![1], vec![5], vec![2, 4], vec![3], vec![0]]);
+    }
+}
@@ -0,0 +1,262 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+
+
+
+
+// VOLUMES {{{
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb
+{
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+
+impl Aabb
+{
+    // an empty point set gives a degenerate box at the origin
+    pub fn from_points(points: &[[f32; 3]]) -> Self
+    {
+        if points.is_empty()
+        {
+            return Self { min: Point3::origin(), max: Point3::origin() };
+        }
+        let mut min = Point3::from(points[0]);
+        let mut max = min;
+        for p in points
+        {
+            for i in 0..3
+            {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Point3<f32> { self.min.midpoint(self.max) }
+    pub fn extents(&self) -> Vector3<f32> { (self.max - self.min) / 2.0 }
+
+    // box around the transformed box, grows under rotation
+    pub fn transform(&self, m: &Matrix4<f32>) -> Self
+    {
+        let center = m.transform_point(self.center());
+        let e = self.extents();
+        let extents = Vector3::new(
+            m.x.x.abs() * e.x + m.y.x.abs() * e.y + m.z.x.abs() * e.z,
+            m.x.y.abs() * e.x + m.y.y.abs() * e.y + m.z.y.abs() * e.z,
+            m.x.z.abs() * e.x + m.y.z.abs() * e.y + m.z.z.abs() * e.z,
+        );
+        Self { min: center - extents, max: center + extents }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingSphere
+{
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+
+impl BoundingSphere
+{
+    // centered on the box, not minimal but cheap and never too small
+    pub fn from_points(points: &[[f32; 3]], aabb: &Aabb) -> Self
+    {
+        let center = aabb.center();
+        let radius = points.iter()
+            .map(|&p| Point3::from(p).distance2(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    pub fn transform(&self, m: &Matrix4<f32>) -> Self
+    {
+        let scale = m.x.truncate().magnitude()
+            .max(m.y.truncate().magnitude())
+            .max(m.z.truncate().magnitude());
+        Self { center: m.transform_point(self.center), radius: self.radius * scale }
+    }
+}
+// }}}
+
+
+
+
+// FRUSTUM {{{
+// planes are (normal, distance) with the normal pointing inside
+#[derive(Debug, Clone)]
+pub struct Frustum
+{
+    pub planes: Vec<Vector4<f32>>,
+}
+
+
+impl Frustum
+{
+    // clip space is -w <= x,y <= w and 0 <= z <= w, each bound gives one plane in world space.
+    // Planes without a normal are dropped, reverse z with an infinite far plane has one.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self
+    {
+        let r = |i: usize| view_proj.row(i);
+        let planes = [r(3) + r(0), r(3) - r(0), r(3) + r(1), r(3) - r(1), r(2), r(3) - r(2)]
+            .into_iter()
+            .filter_map(|p| {
+                let length = p.truncate().magnitude();
+                (length > 1e-6).then(|| p / length)
+            })
+            .collect();
+        Self { planes }
+    }
+
+    fn distance(plane: &Vector4<f32>, p: Point3<f32>) -> f32
+    {
+        plane.truncate().dot(p.to_vec()) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool
+    {
+        self.planes.iter().all(|plane| Self::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool
+    {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the normal
+            let p = Point3::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            Self::distance(plane, p) >= 0.0
+        })
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::core::camera::Projection;
+    use crate::core::renderer::DepthMode;
+    use cgmath::{Deg, Quaternion};
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere
+    {
+        BoundingSphere { center: Point3::new(x, y, z), radius }
+    }
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb
+    {
+        Aabb { min: min.into(), max: max.into() }
+    }
+
+    // a camera at the origin looking down +y in the z-up world
+    fn frustum(mut projection: Projection, depth: DepthMode) -> Frustum
+    {
+        projection.set_depth_mode(depth);
+        let view = Matrix4::look_to_rh(Point3::origin(), Vector3::unit_y(), Vector3::unit_z());
+        Frustum::from_matrix(projection.calc_matrix() * view)
+    }
+
+    #[test]
+    fn perspective_frustum()
+    {
+        let perspective = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let standard = frustum(perspective.clone(), DepthMode::Standard);
+        let reverse = frustum(perspective, DepthMode::ReverseZ);
+        assert_eq!(standard.planes.len(), 6);
+        // the far plane of the infinite projection has no normal
+        assert_eq!(reverse.planes.len(), 5);
+
+        for f in [&standard, &reverse]
+        {
+            // at y = 10 the view is 5.52 units wide to either side
+            assert!(f.intersects_sphere(&sphere(0.0, 10.0, 0.0, 1.0)));
+            assert!(!f.intersects_sphere(&sphere(0.0, -10.0, 0.0, 1.0)));
+            assert!(!f.intersects_sphere(&sphere(-8.0, 10.0, 0.0, 1.0)));
+            assert!(f.intersects_sphere(&sphere(6.0, 10.0, 0.0, 1.0)));
+            assert!(f.intersects_sphere(&sphere(0.0, 0.0, 0.0, 0.5)));
+
+            assert!(f.intersects_aabb(&aabb([-1.0, 9.0, -1.0], [1.0, 11.0, 1.0])));
+            assert!(!f.intersects_aabb(&aabb([-1.0, -11.0, -1.0], [1.0, -9.0, 1.0])));
+            assert!(!f.intersects_aabb(&aabb([8.0, 9.0, -1.0], [9.0, 11.0, 1.0])));
+            assert!(f.intersects_aabb(&aabb([5.0, 9.0, -1.0], [7.0, 11.0, 1.0])));
+            assert!(f.intersects_aabb(&aabb([-0.5, -0.5, -0.5], [0.5, 0.5, 0.5])));
+        }
+
+        // only the standard projection ends at zfar
+        assert!(!standard.intersects_sphere(&sphere(0.0, 1000.0, 0.0, 1.0)));
+        assert!(reverse.intersects_sphere(&sphere(0.0, 1000.0, 0.0, 1.0)));
+        assert!(standard.intersects_sphere(&sphere(0.0, 100.5, 0.0, 1.0)));
+        assert!(!standard.intersects_aabb(&aabb([-1.0, 999.0, -1.0], [1.0, 1001.0, 1.0])));
+        assert!(reverse.intersects_aabb(&aabb([-1.0, 999.0, -1.0], [1.0, 1001.0, 1.0])));
+    }
+
+    #[test]
+    fn orthographic_frustum()
+    {
+        // ten pixels per unit on 800x600 shows 40 units to either side and 30 up and down
+        let ortho = Projection::orthographic(800, 600, 10.0, 0.1, 100.0);
+        for depth in [DepthMode::Standard, DepthMode::ReverseZ]
+        {
+            let f = frustum(ortho.clone(), depth);
+            assert_eq!(f.planes.len(), 6);
+            assert!(f.intersects_sphere(&sphere(0.0, 50.0, 0.0, 1.0)));
+            assert!(!f.intersects_sphere(&sphere(0.0, 150.0, 0.0, 1.0)));
+            assert!(f.intersects_sphere(&sphere(0.0, 100.5, 0.0, 1.0)));
+            assert!(f.intersects_sphere(&sphere(41.0, 50.0, 0.0, 2.0)));
+            assert!(!f.intersects_sphere(&sphere(45.0, 50.0, 0.0, 2.0)));
+            assert!(!f.intersects_sphere(&sphere(0.0, 50.0, 33.0, 2.0)));
+
+            assert!(f.intersects_aabb(&aabb([-1.0, 49.0, -1.0], [1.0, 51.0, 1.0])));
+            assert!(!f.intersects_aabb(&aabb([-1.0, -5.0, -1.0], [1.0, -1.0, 1.0])));
+            assert!(f.intersects_aabb(&aabb([39.0, 49.0, -1.0], [42.0, 51.0, 1.0])));
+            assert!(!f.intersects_aabb(&aabb([41.0, 49.0, -1.0], [42.0, 51.0, 1.0])));
+        }
+    }
+
+    #[test]
+    fn rotated_box_grows_to_hold_its_corners()
+    {
+        let b = aabb([0.0, 0.0, 0.0], [2.0, 1.0, 1.0]);
+        let quarter = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)) * Matrix4::from_angle_z(Deg(90.0));
+        let t = b.transform(&quarter);
+        assert!((t.min - Point3::new(4.0, 0.0, 0.0)).magnitude() < 1e-5, "{:?}", t);
+        assert!((t.max - Point3::new(5.0, 2.0, 1.0)).magnitude() < 1e-5, "{:?}", t);
+
+        let m = Matrix4::from(Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Deg(40.0)));
+        let t = b.transform(&m);
+        for corner in 0..8
+        {
+            let p = Point3::new([0.0, 2.0][corner & 1], [0.0, 1.0][corner >> 1 & 1], [0.0, 1.0][corner >> 2]);
+            let p = m.transform_point(p);
+            for i in 0..3
+            {
+                assert!(t.min[i] <= p[i] + 1e-5 && p[i] <= t.max[i] + 1e-5, "{:?} outside {:?}", p, t);
+            }
+        }
+        // a rotated box is looser than the one it came from
+        assert!(t.extents().x > b.extents().x);
+    }
+
+    #[test]
+    fn sphere_takes_the_largest_scale()
+    {
+        let s = sphere(1.0, 0.0, 0.0, 2.0);
+        let m = Matrix4::from_translation(Vector3::new(0.0, 0.0, 4.0))
+            * Matrix4::from_angle_z(Deg(90.0))
+            * Matrix4::from_nonuniform_scale(1.0, 3.0, 2.0);
+        let t = s.transform(&m);
+        assert!((t.center - Point3::new(0.0, 1.0, 4.0)).magnitude() < 1e-5, "{:?}", t);
+        assert!((t.radius - 6.0).abs() < 1e-5);
+    }
+}
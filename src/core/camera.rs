@@ -1,9 +1,12 @@
 
+use crate::core::picking::Ray;
+use crate::core::bounds::Frustum;
+use crate::core::renderer::DepthMode;
+use crate::core::controller::{ CameraController, CameraInput };
 
 use cgmath::*;
 use wgpu::util::DeviceExt;
-use winit::event::*;
-use winit::dpi::PhysicalPosition;
+use winit::dpi::{ PhysicalPosition, PhysicalSize };
 use instant::Duration;
 use std::f32::consts::FRAC_PI_2;
 
@@ -11,16 +14,39 @@ use std::f32::consts::FRAC_PI_2;
 
 
 
+// z' = (z + w) / 2, columns are listed first
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
 );
 
 
-#[derive(Debug)]
+// z' = 1 - z
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0,  0.0, 0.0,
+    0.0, 1.0,  0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0,  1.0, 1.0,
+);
+
+
+// camera rotation looking from eye at target in a z-up world, the camera looks down its z axis with x to the right
+// like FpsController moves it, view_matrix negates the quaternion which leaves the rotation as it is
+pub fn look_rotation(eye: Point3<f32>, target: Point3<f32>) -> Quaternion<f32>
+{
+    let forward = (target - eye).normalize();
+    // straight up or down has no yaw, any right vector will do
+    let right = forward.cross(Vector3::unit_z());
+    let right = if right.magnitude2() < 1e-8 { Vector3::unit_x() } else { right.normalize() };
+    Quaternion::from(Matrix3::from_cols(right, forward.cross(right), forward))
+}
+
+
+#[derive(Debug, Clone)]
 pub struct CameraState
 {
     pub position: Point3<f32>,
@@ -31,10 +57,14 @@ pub struct CameraState
 #[derive(Debug)]
 pub struct Camera {
     pub state: CameraState,
+    // the state before the last update, rendering blends from it towards state
+    pub previous: CameraState,
     pub bind_group: wgpu::BindGroup,
     pub buffer : wgpu::Buffer,
     pub uniform: CameraUniform,
-    pub controller: CameraController,
+    pub input: CameraInput,
+    pub controllers: Vec<Box<dyn CameraController>>,
+    pub active: usize,
     pub projection: Projection,
 }
 
@@ -49,7 +79,7 @@ impl Camera {
         yaw: Y,
         pitch: P,
         projection: Projection,
-        controller: CameraController,
+        controllers: Vec<Box<dyn CameraController>>,
         device: &wgpu::Device,
         bind_group_layout: &wgpu::BindGroupLayout,
     ) -> Self
@@ -87,11 +117,14 @@ impl Camera {
 
 
         let mut cam = Self {
+            previous: state.clone(),
             state,
             bind_group: camera_bind_group,
             buffer: camera_buffer,
             uniform: camera_uniform,
-            controller,
+            input: CameraInput::new(),
+            controllers,
+            active: 0,
             projection,
         };
         cam.update_view_proj();
@@ -107,115 +140,128 @@ impl Camera {
     }
 
 
-    pub fn update_fps(&mut self, dt: Duration) 
+    pub fn update(&mut self, dt: Duration)
     {
-        let c = &mut self.controller;
-        let v = &mut self.state.velocity;
-        *v += (c.force * c.speed * 0.06 ) / (v.magnitude() + 1.0);
-        let dt = dt.as_secs_f32();
-        let forward = self.state.rotation * Vector3::unit_z();
-        let right = self.state.rotation * Vector3::unit_x();
-        let yaw =  Quaternion::from_angle_z(Rad(-c.mouse_dx) * c.sensitivity * dt);  // world z
-        let pitch =  Quaternion::from_angle_x(Rad(-c.mouse_dy) * c.sensitivity * dt);  // world x
-
-        self.state.position += forward * v.z * c.speed * dt;
-        self.state.position += right * v.x * c.speed * dt;
-        self.state.position += forward * c.scroll * c.speed * c.sensitivity * dt;
-        let mut new_fovy = self.projection.fovy + Rad(1.0) * c.scroll * c.speed * c.sensitivity * dt * 0.1;
-        if new_fovy < Rad(0.1) { new_fovy = Rad(0.1); }
-        if new_fovy > Rad(1.5) { new_fovy = Rad(1.5); }
-        self.projection.set_fovy(new_fovy);
-        self.state.position.z += v.y * c.speed * dt;
-        if self.state.position.z < 0.4 { self.state.position.z = 0.4; }
-
-        c.scroll = 0.0;
-        c.mouse_dx = 0.0;
-        c.mouse_dy = 0.0;
-        *v *= 0.9;
-        self.state.rotation = yaw * self.state.rotation * pitch; 
+        self.previous = self.state.clone();
+        self.controllers[self.active].update(&mut self.state, &mut self.projection, &self.input, dt);
+        self.input.reset();
+    }
 
+
+    pub fn controller(&self) -> &dyn CameraController
+    {
+        self.controllers[self.active].as_ref()
     }
 
-    pub fn update_orbit(&mut self, dt: Duration) 
+
+    // the new controller starts from rest instead of inheriting the old one's momentum
+    pub fn set_controller(&mut self, i: usize) -> &dyn CameraController
     {
-        let c = &mut self.controller;
-        let v = &mut self.state.velocity;
-        *v += (c.force * c.speed * 0.06 ) / (v.magnitude() + 1.0);
-        let dt = dt.as_secs_f32();
-        let forward = self.state.rotation * Vector3::unit_z();
-        let yaw =  Quaternion::from_angle_z(Rad(-c.mouse_dx) * c.sensitivity * dt);                                             // world z
-        let pitch =  Quaternion::from_axis_angle(self.state.rotation * Vector3::unit_x(), Rad(-c.mouse_dy) * c.sensitivity * dt); // current x
-        let rotation = yaw * pitch;
-        let point_as_quat = Quaternion::new(0.0, self.state.position.x, self.state.position.y, self.state.position.z);
-        let rotated_quat = rotation * point_as_quat * rotation.invert();
-        let rotated_point = Point3::new(rotated_quat.v.x, rotated_quat.v.y, rotated_quat.v.z);
-
-        self.state.position = rotated_point;
-        self.state.position += forward * v.z * c.speed * dt;
-        self.state.position.z += v.y * c.speed * dt;
-        self.state.position.x += v.x * c.speed * dt;
-        self.state.position.y += c.scroll * c.speed * c.sensitivity * dt;
-
-        c.scroll *= 0.0;
-        c.mouse_dx = 0.0;
-        c.mouse_dy = 0.0;
-        *v *= 0.9;
-        self.state.rotation = rotation * self.state.rotation;
+        self.active = i;
+        self.state.velocity = Vector3::zero();
+        self.controller()
+    }
+
 
+    pub fn next_controller(&mut self) -> &dyn CameraController
+    {
+        self.set_controller((self.active + 1) % self.controllers.len())
     }
 
-    pub fn update_2d(&mut self, dt: Duration) 
+
+    // the camera alpha of the way from the previous update to the last one
+    pub fn interpolated(&self, alpha: f32) -> CameraState
     {
-        let c = &mut self.controller;
-        let v = &mut self.state.velocity;
-        *v += (c.force * c.speed * 0.06 ) / (v.magnitude() + 1.0);
-        let dt = dt.as_secs_f32();
-        let forward = self.state.rotation * Vector3::unit_z();
-        let right = self.state.rotation * Vector3::unit_x();
-        let up = self.state.rotation * Vector3::unit_y();
-        let yaw =  Quaternion::from_angle_z(Rad(-c.mouse_dx) * c.sensitivity * dt);  // world z
-        let pitch =  Quaternion::from_angle_x(Rad(-c.mouse_dy) * c.sensitivity * dt);  // world x
-
-        // self.state.position += forward * v.z * c.speed * dt;
-        // self.state.position += right * v.x * c.speed * dt;
-        // self.state.position += forward * c.scroll * c.speed * c.sensitivity * dt;
-        // self.state.position += up * v.y * c.speed * c.sensitivity * dt;
-        self.state.position.x = c.mouse_pos.x as f32;
-        self.state.position.z = c.mouse_pos.y as f32;
-        let mut new_fovy = self.projection.fovy + Rad(1.0) * c.scroll * c.speed * c.sensitivity * dt * 0.1;
-        if new_fovy < Rad(0.1) { new_fovy = Rad(0.1); }
-        if new_fovy > Rad(1.5) { new_fovy = Rad(1.5); }
-        self.projection.set_fovy(new_fovy);
-        // self.state.position.z += v.y * c.speed * dt;
-        // if self.state.position.z < 0.4 { self.state.position.z = 0.4; }
-        c.scroll = 0.0;
-        c.mouse_dx = 0.0;
-        c.mouse_dy = 0.0;
-        *v *= 0.9;
-        // self.state.rotation = yaw * self.state.rotation * pitch;
+        let (a, b) = (&self.previous, &self.state);
+        let rotation = if a.rotation.dot(b.rotation) < 0.0 { -b.rotation } else { b.rotation };
+        CameraState
+        {
+            position: a.position + (b.position - a.position) * alpha,
+            rotation: a.rotation.nlerp(rotation, alpha),
+            velocity: b.velocity,
+        }
     }
 
 
     pub fn calc_matrix(&self) -> Matrix4<f32> 
     {        
+        self.view_matrix(&self.state)
+    }
+
+
+    fn view_matrix(&self, state: &CameraState) -> Matrix4<f32>
+    {
         // world is z-up, camera is y-up z-forward
-        Matrix4::look_to_rh
+        let mut view = Matrix4::look_to_rh
         (
-            self.state.position,
-            -self.state.rotation * Vector3::unit_z(),
+            state.position,
+            -state.rotation * Vector3::unit_z(),
             Vector3::unit_z(),
-        )
+        );
+        if let Some(zoom) = self.projection.zoom()
+        {
+            // snap the view to whole screen pixels so texels never land between two pixels
+            view.w.x = (view.w.x * zoom).round() / zoom;
+            view.w.y = (view.w.y * zoom).round() / zoom;
+        }
+        view
+    }
+
+
+    pub fn view_proj(&self) -> Matrix4<f32>
+    {
+        self.projection.calc_matrix() * self.calc_matrix()
+    }
+
+
+    pub fn frustum(&self) -> Frustum
+    {
+        Frustum::from_matrix(self.view_proj())
+    }
+
+
+    // world space ray through a cursor position in window pixels
+    pub fn screen_ray(&self, cursor: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Ray
+    {
+        cursor_ray(self.view_proj(), self.projection.depth, cursor, size)
     }
 
 }
 
 
 
-#[derive(Debug)]
+pub fn cursor_ray(view_proj: Matrix4<f32>, depth: DepthMode, cursor: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Ray
+{
+    let x = (2.0 * cursor.x / size.width as f64 - 1.0) as f32;
+    let y = (1.0 - 2.0 * cursor.y / size.height as f64) as f32;
+    // inverting the full view projection also undoes the z-up world / y-up camera swap in calc_matrix
+    let inverse = view_proj
+        .invert()
+        .unwrap_or(Matrix4::identity());
+    let unproject = |depth: f32| Point3::from_homogeneous(inverse * Vector4::new(x, y, depth, 1.0));
+    // 0.5 is finite in both depth modes, reverse z puts infinity at 0.0
+    let near = unproject(depth.near_value());
+    let far = unproject(0.5);
+    Ray::new(near, far - near)
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub enum ProjectionMode
+{
+    Perspective { fovy: Rad<f32> },
+    // one world unit covers zoom screen pixels
+    Orthographic { zoom: f32 },
+}
+
+
+#[derive(Debug, Clone)]
 pub struct Projection 
 {
-    aspect: f32,
-    fovy: Rad<f32>,
+    width: u32,
+    height: u32,
+    pub mode: ProjectionMode,
+    pub depth: DepthMode,
     znear: f32,
     zfar: f32,
 }
@@ -229,8 +275,27 @@ impl Projection {
         zfar: f32,
     ) -> Self {
         Self {
-            aspect: width as f32 / height as f32,
-            fovy: fovy.into(),
+            width,
+            height,
+            mode: ProjectionMode::Perspective { fovy: fovy.into() },
+            depth: DepthMode::Standard,
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn orthographic(
+        width: u32,
+        height: u32,
+        zoom: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            mode: ProjectionMode::Orthographic { zoom: zoom.round().max(1.0) },
+            depth: DepthMode::Standard,
             znear,
             zfar,
         }
@@ -238,107 +303,97 @@ impl Projection {
 
     pub fn resize(&mut self, width: u32, height: u32) 
     {
-        self.aspect = width as f32 / height as f32;
+        self.width = width;
+        self.height = height;
     }
 
-    pub fn set_fovy(&mut self, fovy: Rad<f32>) 
+    pub fn aspect(&self) -> f32
     {
-        self.fovy = fovy;
+        self.width as f32 / self.height as f32
     }
 
-
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+    pub fn fovy(&self) -> Option<Rad<f32>>
+    {
+        match self.mode
+        {
+            ProjectionMode::Perspective { fovy } => Some(fovy),
+            ProjectionMode::Orthographic { .. } => None,
+        }
     }
-}
 
-
-
-#[derive(Debug)]
-pub struct CameraController 
-{
-    force: Vector3<f32>,
-    mouse_dx: f32,
-    mouse_dy: f32,
-    mouse_pos: PhysicalPosition<f64>,
-    scroll: f32,
-    speed: f32,
-    sensitivity: f32,
-}
- 
-impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
-        Self {
-            force: Vector3::zero(),
-            mouse_dx: 0.0,
-            mouse_dy: 0.0,
-            mouse_pos: PhysicalPosition::new(0.0, 0.0),
-            scroll: 0.0,
-            speed,
-            sensitivity,
+    pub fn zoom(&self) -> Option<f32>
+    {
+        match self.mode
+        {
+            ProjectionMode::Perspective { .. } => None,
+            ProjectionMode::Orthographic { zoom } => Some(zoom),
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool{
-        let amount = match state {
-            ElementState::Pressed => 1.0,
-            ElementState::Released => 0.0,
-        };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.force.z = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.force.z = -amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.force.x = -amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.force.x = amount;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.force.y = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.force.y = -amount;
-                true
-            }
-            _ => false,
+    // screen pixels covered by one world unit at distance from the eye
+    pub fn pixels_per_unit(&self, distance: f32) -> f32
+    {
+        match self.mode
+        {
+            ProjectionMode::Perspective { fovy } =>
+                self.height as f32 / (2.0 * distance.max(self.znear) * (fovy.0 / 2.0).tan()),
+            ProjectionMode::Orthographic { zoom } => zoom,
         }
     }
 
-    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.mouse_dx = mouse_dx as f32;
-        self.mouse_dy = mouse_dy as f32;
+    pub fn set_fovy(&mut self, fovy: Rad<f32>) 
+    {
+        if let ProjectionMode::Perspective { fovy: f } = &mut self.mode { *f = fovy; }
     }
 
+    // reverse z perspective projections drop zfar and use an infinite far plane
+    pub fn set_depth_mode(&mut self, depth: DepthMode)
+    {
+        self.depth = depth;
+    }
 
-    pub fn process_mouse_pos(&mut self, x:f64, y:f64 ) {
-        self.mouse_pos.x = x as f64;
-        self.mouse_pos.y = y as f64;
+    pub fn set_zoom(&mut self, zoom: f32)
+    {
+        if let ProjectionMode::Orthographic { zoom: z } = &mut self.mode { *z = zoom.round().max(1.0); }
     }
 
-    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
-            // I'm assuming a line is about 100 pixels
-            MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
-            MouseScrollDelta::PixelDelta(PhysicalPosition {
-                y: scroll,
-                ..
-            }) => *scroll as f32,
-        };
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        match (self.mode, self.depth)
+        {
+            (ProjectionMode::Perspective { fovy }, DepthMode::Standard) => 
+                OPENGL_TO_WGPU_MATRIX * perspective(fovy, self.aspect(), self.znear, self.zfar),
+            (ProjectionMode::Perspective { fovy }, DepthMode::ReverseZ) =>
+            {
+                // depth = znear / -z, 1.0 on the near plane and 0.0 at infinity
+                let f = 1.0 / (fovy / 2.0).tan();
+                Matrix4::new(
+                    f / self.aspect(), 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.znear, 0.0,
+                )
+            }
+            (ProjectionMode::Orthographic { zoom }, depth) =>
+            {
+                // odd sizes put the extra pixel on the right/top so the origin stays on a pixel corner
+                let left = -((self.width / 2) as f32) / zoom;
+                let right = self.width.div_ceil(2) as f32 / zoom;
+                let bottom = -((self.height / 2) as f32) / zoom;
+                let top = self.height.div_ceil(2) as f32 / zoom;
+                let matrix = OPENGL_TO_WGPU_MATRIX * ortho(left, right, bottom, top, self.znear, self.zfar);
+                match depth
+                {
+                    DepthMode::Standard => matrix,
+                    DepthMode::ReverseZ => REVERSE_Z_MATRIX * matrix,
+                }
+            }
+        }
     }
 }
 
 
 
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
@@ -359,3 +414,28 @@ impl CameraUniform {
     }
 }
 
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn centre_ray_follows_forward_under_reverse_z()
+    {
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        projection.set_depth_mode(DepthMode::ReverseZ);
+        let eye = Point3::new(1.0, -2.0, 3.0);
+        let target = Point3::new(4.0, 5.0, 0.5);
+        let rotation = look_rotation(eye, target);
+        // the same view calc_matrix builds from a camera state
+        let view = Matrix4::look_to_rh(eye, -rotation * Vector3::unit_z(), Vector3::unit_z());
+        let ray = cursor_ray(projection.calc_matrix() * view, projection.depth, PhysicalPosition::new(400.0, 300.0), PhysicalSize::new(800, 600));
+        let forward = (target - eye).normalize();
+        assert!((ray.direction - forward).magnitude() < 1e-4, "{:?} vs {:?}", ray.direction, forward);
+        // the ray starts on the near plane in front of the eye
+        assert!(((ray.origin - eye).dot(forward) - 0.1).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::core::camera::{Camera, CameraState};
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Vector3, Vector4};
+use serde::{Deserialize, Serialize};
+use instant::Duration;
+
+
+
+
+// FORMAT {{{
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PathSample
+{
+    pub time: f32,
+    pub position: [f32; 3],
+    // s, x, y, z
+    pub rotation: [f32; 4],
+}
+
+
+impl PathSample
+{
+    pub fn position(&self) -> Point3<f32> { self.position.into() }
+
+    pub fn rotation(&self) -> Quaternion<f32>
+    {
+        let [s, x, y, z] = self.rotation;
+        Quaternion::new(s, x, y, z)
+    }
+}
+
+
+// camera states over time, recorded once per frame and saved as ron
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath
+{
+    pub samples: Vec<PathSample>,
+}
+// }}}
+
+
+
+
+// CAMERA PATH {{{
+impl CameraPath
+{
+    pub fn new() -> Self
+    {
+        Self { samples: Vec::new() }
+    }
+
+    pub fn duration(&self) -> f32 { self.samples.last().map_or(0.0, |s| s.time) }
+    pub fn is_empty(&self) -> bool { self.samples.is_empty() }
+
+
+    // appends the state dt after the previous sample, the first one lands at 0
+    pub fn record(&mut self, dt: Duration, state: &CameraState)
+    {
+        let time = self.samples.last().map_or(0.0, |s| s.time + dt.as_secs_f32());
+        let r = state.rotation;
+        self.samples.push(PathSample { time, position: state.position.into(), rotation: [r.s, r.v.x, r.v.y, r.v.z] });
+    }
+
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()>
+    {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self>
+    {
+        let text = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+
+    // averages the raw per frame samples into keys every interval seconds, takes the hand jitter out
+    // of a recording before the spline goes through it
+    pub fn smooth(&self, interval: f32) -> CameraPath
+    {
+        let (Some(first), Some(last)) = (self.samples.first(), self.samples.last()) else { return self.clone() };
+        if interval <= 0.0 { return self.clone(); }
+
+        let keys = ((last.time - first.time) / interval).ceil().max(1.0) as usize;
+        let mut samples = Vec::with_capacity(keys + 1);
+        for k in 0..=keys
+        {
+            let time = (first.time + k as f32 * interval).min(last.time);
+            let window = self.samples.iter().filter(|s| (s.time - time).abs() <= interval * 0.5);
+
+            let mut position = Vector3::zero();
+            let mut rotation = Vector4::zero();
+            let mut count = 0.0;
+            let reference = self.sample(time).map_or(Quaternion::one(), |(_, r)| r);
+            for s in window
+            {
+                position += s.position().to_vec();
+                // q and -q are the same rotation, keep all of them on one side before adding
+                let r = s.rotation();
+                let r = if r.dot(reference) < 0.0 { -r } else { r };
+                rotation += Vector4::new(r.s, r.v.x, r.v.y, r.v.z);
+                count += 1.0;
+            }
+
+            let (position, rotation) = if count > 0.0
+            {
+                let r = rotation.normalize();
+                (Point3::from_vec(position / count), Quaternion::new(r.x, r.y, r.z, r.w))
+            }
+            else
+            {
+                self.sample(time).unwrap()
+            };
+            samples.push(PathSample
+            {
+                time,
+                position: position.into(),
+                rotation: [rotation.s, rotation.v.x, rotation.v.y, rotation.v.z],
+            });
+        }
+        samples.dedup_by(|b, a| b.time <= a.time);
+        CameraPath { samples }
+    }
+
+
+    // catmull-rom through the samples, uneven spacing is fine since the tangents are per second
+    pub fn sample(&self, time: f32) -> Option<(Point3<f32>, Quaternion<f32>)>
+    {
+        let first = self.samples.first()?;
+        let last = self.samples.last()?;
+        if time <= first.time { return Some((first.position(), first.rotation())); }
+        if time >= last.time { return Some((last.position(), last.rotation())); }
+
+        let n = self.samples.len();
+        let i = self.samples.partition_point(|s| s.time <= time) - 1;
+        let (a, b) = (&self.samples[i], &self.samples[i + 1]);
+        let before = &self.samples[i.saturating_sub(1)];
+        let after = &self.samples[(i + 2).min(n - 1)];
+
+        let h = b.time - a.time;
+        let s = (time - a.time) / h;
+        let (s2, s3) = (s * s, s * s * s);
+        let weights = [2.0 * s3 - 3.0 * s2 + 1.0, (s3 - 2.0 * s2 + s) * h, -2.0 * s3 + 3.0 * s2, (s3 - s2) * h];
+        let tangent = |p: Vector4<f32>, n: Vector4<f32>, dt: f32| if dt > 0.0 { (n - p) / dt } else { Vector4::zero() };
+        let hermite = |p0: Vector4<f32>, p1: Vector4<f32>, p2: Vector4<f32>, p3: Vector4<f32>|
+        {
+            let m1 = tangent(p0, p2, b.time - before.time);
+            let m2 = tangent(p1, p3, after.time - a.time);
+            p1 * weights[0] + m1 * weights[1] + p2 * weights[2] + m2 * weights[3]
+        };
+
+        let point = |s: &PathSample| s.position().to_homogeneous();
+        let position = hermite(point(before), point(a), point(b), point(after));
+
+        // the rotation spline runs on the 4d components, each one flipped next to its neighbour
+        let mut quats = [before.rotation(), a.rotation(), b.rotation(), after.rotation()];
+        for k in 1..4
+        {
+            if quats[k].dot(quats[k - 1]) < 0.0 { quats[k] = -quats[k]; }
+        }
+        let [q0, q1, q2, q3] = quats.map(|q| Vector4::new(q.s, q.v.x, q.v.y, q.v.z));
+        let r = hermite(q0, q1, q2, q3).normalize();
+
+        Some((Point3::new(position.x, position.y, position.z), Quaternion::new(r.x, r.y, r.z, r.w)))
+    }
+
+
+    pub fn apply(&self, time: f32, camera: &mut Camera)
+    {
+        if let Some((position, rotation)) = self.sample(time)
+        {
+            camera.state.position = position;
+            camera.state.rotation = rotation;
+            camera.state.velocity = Vector3::zero();
+        }
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use cgmath::{Deg, Rotation3};
+
+    fn state(x: f32, degrees: f32) -> CameraState
+    {
+        CameraState
+        {
+            position: Point3::new(x, x * x, 1.0),
+            rotation: Quaternion::from_angle_z(Deg(degrees)),
+            velocity: Vector3::zero(),
+        }
+    }
+
+    fn same_rotation(a: Quaternion<f32>, b: Quaternion<f32>) -> bool { a.dot(b).abs() > 0.9999 }
+
+    fn recorded() -> CameraPath
+    {
+        let mut path = CameraPath::new();
+        for (i, dt) in [0.0, 0.016, 0.033, 0.017, 0.05].into_iter().enumerate()
+        {
+            path.record(Duration::from_secs_f32(dt), &state(i as f32, i as f32 * 20.0));
+        }
+        path
+    }
+
+    #[test]
+    fn record_save_load_round_trip()
+    {
+        let path = recorded();
+        assert_eq!(path.samples[0].time, 0.0);
+        assert!((path.duration() - 0.116).abs() < 1e-5);
+
+        let file = std::env::temp_dir().join(format!("fstop_camera_path_{}.ron", std::process::id()));
+        path.save(&file).unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        std::fs::remove_file(&file).ok();
+        assert_eq!(loaded.samples.len(), path.samples.len());
+        for (a, b) in path.samples.iter().zip(&loaded.samples)
+        {
+            assert_eq!((a.time, a.position, a.rotation), (b.time, b.position, b.rotation));
+        }
+    }
+
+    #[test]
+    fn sample_passes_through_the_keys()
+    {
+        let path = recorded();
+        for key in &path.samples
+        {
+            let (position, rotation) = path.sample(key.time).unwrap();
+            assert!((position - key.position()).magnitude() < 1e-4, "{:?} != {:?}", position, key.position());
+            assert!(same_rotation(rotation, key.rotation()));
+        }
+    }
+
+    #[test]
+    fn quaternions_are_flipped_onto_one_side()
+    {
+        // the same rotations stored with alternating signs
+        let mut path = CameraPath::new();
+        for i in 0..4
+        {
+            let mut s = state(i as f32, i as f32 * 10.0);
+            if i % 2 == 1 { s.rotation = -s.rotation; }
+            path.record(Duration::from_secs_f32(1.0), &s);
+        }
+        for i in 0..6
+        {
+            let time = 0.5 * i as f32;
+            let (_, rotation) = path.sample(time).unwrap();
+            assert!((rotation.magnitude() - 1.0).abs() < 1e-4);
+            assert!(same_rotation(rotation, Quaternion::from_angle_z(Deg(time * 10.0))), "at {}: {:?}", time, rotation);
+        }
+
+        // averaging q and -q must not cancel out
+        let smoothed = path.smooth(2.0);
+        for key in &smoothed.samples
+        {
+            let expected = path.sample(key.time).unwrap().1;
+            assert!(key.rotation().dot(expected).abs() > 0.99, "at {}: {:?}", key.time, key.rotation());
+        }
+    }
+}
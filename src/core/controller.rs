@@ -0,0 +1,409 @@
+use crate::core::camera::{ CameraState, Projection, look_rotation };
+use crate::core::input::Input;
+
+use cgmath::*;
+use winit::dpi::PhysicalPosition;
+use instant::Duration;
+
+
+
+
+// CAMERA INPUT {{{
+// Raw input gathered between two updates, the deltas are cleared by Camera::update
+#[derive(Debug)]
+pub struct CameraInput
+{
+    pub force: Vector3<f32>,
+    pub mouse_dx: f32,
+    pub mouse_dy: f32,
+    // cursor position normalized to 0..1 over the window
+    pub mouse_pos: PhysicalPosition<f64>,
+    pub dragging: bool,
+    pub scroll: f32,
+}
+
+impl CameraInput {
+    pub fn new() -> Self {
+        Self {
+            force: Vector3::zero(),
+            mouse_dx: 0.0,
+            mouse_dy: 0.0,
+            mouse_pos: PhysicalPosition::new(0.0, 0.0),
+            dragging: false,
+            scroll: 0.0,
+        }
+    }
+
+    // movement, dragging and zoom come from the input map, see assets/input.ron
+    pub fn read_actions(&mut self, input: &Input) {
+        self.force = Vector3::new(input.axis("move_right"), input.axis("move_up"), input.axis("move_forward"));
+        self.dragging = input.held("drag");
+        self.scroll += input.axis("zoom");
+    }
+
+    // several motion events can arrive between two updates
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.mouse_dx += mouse_dx as f32;
+        self.mouse_dy += mouse_dy as f32;
+    }
+
+
+    pub fn process_mouse_pos(&mut self, x:f64, y:f64 ) {
+        self.mouse_pos.x = x;
+        self.mouse_pos.y = y;
+    }
+
+    pub fn reset(&mut self) {
+        self.scroll = 0.0;
+        self.mouse_dx = 0.0;
+        self.mouse_dy = 0.0;
+    }
+}
+
+impl Default for CameraInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+// }}}
+
+
+
+
+// PHYSICS {{{
+// Velocity decays by e^(-DAMPING * t), roughly the old 0.9 per frame at 60 fps
+pub const DAMPING: f32 = 6.0;
+// Top speed reached while a key is held, in multiples of the controller speed
+pub const TOP_SPEED: f32 = 2.0;
+// Radians per pixel of mouse motion at sensitivity 1.0
+pub const LOOK_SCALE: f32 = 1.0 / 60.0;
+// Scroll is an impulse, one wheel line (100) moves speed * sensitivity * 100 * SCROLL_SCALE units
+pub const SCROLL_SCALE: f32 = 1.0 / 60.0;
+
+
+// Exact solution of dv/dt = a - DAMPING * v over dt, returns the distance travelled.
+// Any number of steps covering the same time lands on the same curve, unlike scaling v per frame.
+pub fn integrate(velocity: &mut Vector3<f32>, acceleration: Vector3<f32>, dt: f32) -> Vector3<f32>
+{
+    let terminal = acceleration / DAMPING;
+    let decay = (-DAMPING * dt).exp();
+    let displacement = terminal * dt + (*velocity - terminal) * ((1.0 - decay) / DAMPING);
+    *velocity = terminal + (*velocity - terminal) * decay;
+    displacement
+}
+// }}}
+
+
+
+
+// CONTROLLERS {{{
+pub trait CameraController : std::fmt::Debug
+{
+    fn name(&self) -> &'static str;
+
+    fn update(
+        &mut self,
+        state: &mut CameraState,
+        projection: &mut Projection,
+        input: &CameraInput,
+        dt: Duration);
+}
+
+
+// screen axes as Camera::calc_matrix builds them, world is z-up
+fn screen_axes(rotation: Quaternion<f32>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>)
+{
+    let look = -rotation * Vector3::unit_z();
+    // straight up or down has no yaw, fall back to the camera's own right vector
+    let right = look.cross(Vector3::unit_z());
+    let right = if right.magnitude2() < 1e-8 { rotation * Vector3::unit_x() } else { right.normalize() };
+    let up = right.cross(look);
+    (look, right, up)
+}
+
+
+fn zoom_fovy(projection: &mut Projection, amount: f32)
+{
+    if let Some(fovy) = projection.fovy()
+    {
+        let mut new_fovy = fovy + Rad(1.0) * amount;
+        if new_fovy < Rad(0.1) { new_fovy = Rad(0.1); }
+        if new_fovy > Rad(1.5) { new_fovy = Rad(1.5); }
+        projection.set_fovy(new_fovy);
+    }
+}
+
+
+
+#[derive(Debug)]
+pub struct FpsController
+{
+    pub speed: f32,
+    pub sensitivity: f32,
+}
+
+impl FpsController
+{
+    pub fn new(speed: f32, sensitivity: f32) -> Self { Self { speed, sensitivity } }
+}
+
+impl CameraController for FpsController
+{
+    fn name(&self) -> &'static str { "fps" }
+
+    fn update(&mut self, state: &mut CameraState, projection: &mut Projection, input: &CameraInput, dt: Duration)
+    {
+        let dt = dt.as_secs_f32();
+        let d = integrate(&mut state.velocity, input.force * self.speed * TOP_SPEED * DAMPING, dt);
+        let forward = state.rotation * Vector3::unit_z();
+        let right = state.rotation * Vector3::unit_x();
+        let yaw =  Quaternion::from_angle_z(Rad(-input.mouse_dx) * self.sensitivity * LOOK_SCALE);  // world z
+        let pitch =  Quaternion::from_angle_x(Rad(-input.mouse_dy) * self.sensitivity * LOOK_SCALE);  // world x
+
+        state.position += forward * d.z;
+        state.position += right * d.x;
+        state.position += forward * input.scroll * self.speed * self.sensitivity * SCROLL_SCALE;
+        zoom_fovy(projection, input.scroll * self.speed * self.sensitivity * SCROLL_SCALE * 0.1);
+        state.position.z += d.y;
+        if state.position.z < 0.4 { state.position.z = 0.4; }
+
+        state.rotation = yaw * state.rotation * pitch;
+    }
+}
+
+
+
+#[derive(Debug)]
+pub struct OrbitController
+{
+    pub target: Point3<f32>,
+    pub speed: f32,
+    pub sensitivity: f32,
+}
+
+impl OrbitController
+{
+    pub fn new(target: Point3<f32>, speed: f32, sensitivity: f32) -> Self { Self { target, speed, sensitivity } }
+}
+
+impl CameraController for OrbitController
+{
+    fn name(&self) -> &'static str { "orbit" }
+
+    fn update(&mut self, state: &mut CameraState, _projection: &mut Projection, input: &CameraInput, dt: Duration)
+    {
+        let dt = dt.as_secs_f32();
+        let d = integrate(&mut state.velocity, input.force * self.speed * TOP_SPEED * DAMPING, dt);
+        let forward = state.rotation * Vector3::unit_z();
+        let yaw =  Quaternion::from_angle_z(Rad(-input.mouse_dx) * self.sensitivity * LOOK_SCALE);                                        // world z
+        let pitch =  Quaternion::from_axis_angle(state.rotation * Vector3::unit_x(), Rad(-input.mouse_dy) * self.sensitivity * LOOK_SCALE); // current x
+        let rotation = yaw * pitch;
+
+        state.position = self.target + rotation.rotate_vector(state.position - self.target);
+        state.position += forward * d.z;
+        state.position.z += d.y;
+        state.position.x += d.x;
+        state.position.y += input.scroll * self.speed * self.sensitivity * SCROLL_SCALE;
+
+        state.rotation = rotation * state.rotation;
+    }
+}
+
+
+
+// WASD pans the screen plane, scroll zooms in whole steps on orthographic projections and dollies otherwise
+#[derive(Debug)]
+pub struct PanZoomController
+{
+    pub speed: f32,
+}
+
+impl PanZoomController
+{
+    pub fn new(speed: f32) -> Self { Self { speed } }
+}
+
+impl CameraController for PanZoomController
+{
+    fn name(&self) -> &'static str { "pan zoom" }
+
+    fn update(&mut self, state: &mut CameraState, projection: &mut Projection, input: &CameraInput, dt: Duration)
+    {
+        let dt = dt.as_secs_f32();
+        let d = integrate(&mut state.velocity, input.force * self.speed * TOP_SPEED * DAMPING, dt);
+        let (look, right, up) = screen_axes(state.rotation);
+
+        match projection.zoom()
+        {
+            Some(zoom) =>
+            {
+                // pan in screen pixels, so the speed feels the same at every zoom level
+                let pixels = 40.0 / zoom;
+                state.position += right * d.x * pixels;
+                state.position += up * d.z * pixels;
+                if input.scroll != 0.0
+                {
+                    // whole steps only, fractional zoom breaks the 1:1 pixel mapping
+                    projection.set_zoom((zoom - input.scroll.signum()).clamp(1.0, 16.0));
+                }
+            }
+            None =>
+            {
+                state.position += right * d.x;
+                state.position += up * d.z;
+                state.position -= look * input.scroll * self.speed * SCROLL_SCALE * 0.1;
+            }
+        }
+    }
+}
+
+
+
+// Right drag rolls the scene on a virtual sphere around the target, scroll changes the distance
+// The camera stays upright and short of the poles, the view matrix has no roll to show
+const ARCBALL_MAX_PITCH: f32 = 1.55;
+
+#[derive(Debug)]
+pub struct ArcballController
+{
+    pub target: Point3<f32>,
+    pub speed: f32,
+    last: Option<Vector3<f32>>,
+}
+
+impl ArcballController
+{
+    pub fn new(target: Point3<f32>, speed: f32) -> Self { Self { target, speed, last: None } }
+
+    // cursor in 0..1 window space to a point on the unit sphere, camera space with z towards the viewer
+    fn project(cursor: PhysicalPosition<f64>) -> Vector3<f32>
+    {
+        let x = (2.0 * cursor.x - 1.0) as f32;
+        let y = (1.0 - 2.0 * cursor.y) as f32;
+        let d = x * x + y * y;
+        if d <= 1.0 { Vector3::new(x, y, (1.0 - d).sqrt()) }
+        else { Vector3::new(x, y, 0.0).normalize() }
+    }
+}
+
+impl CameraController for ArcballController
+{
+    fn name(&self) -> &'static str { "arcball" }
+
+    fn update(&mut self, state: &mut CameraState, _projection: &mut Projection, input: &CameraInput, _dt: Duration)
+    {
+        let (look, right, up) = screen_axes(state.rotation);
+        let to_world = |p: Vector3<f32>| right * p.x + up * p.y - look * p.z;
+
+        let current = Self::project(input.mouse_pos);
+        if input.dragging
+        {
+            if let Some(last) = self.last
+            {
+                // rotating the scene by q is the same as moving the camera by q^-1
+                let q = Quaternion::between_vectors(to_world(last), to_world(current));
+                let q = q.invert();
+                let offset = q.rotate_vector(state.position - self.target);
+                let distance = offset.magnitude();
+                let pitch = (offset.z / distance).asin().clamp(-ARCBALL_MAX_PITCH, ARCBALL_MAX_PITCH);
+                let yaw = offset.y.atan2(offset.x);
+                let offset = Vector3::new(pitch.cos() * yaw.cos(), pitch.cos() * yaw.sin(), pitch.sin()) * distance;
+                state.position = self.target + offset;
+                state.rotation = look_rotation(state.position, self.target);
+            }
+            self.last = Some(current);
+        }
+        else
+        {
+            self.last = None;
+        }
+
+        let offset = state.position - self.target;
+        let distance = (offset.magnitude() * (1.0 + input.scroll * self.speed * SCROLL_SCALE * 0.01)).max(0.5);
+        state.position = self.target + offset.normalize() * distance;
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn simulate(controller: &mut dyn CameraController, fps: u32) -> Point3<f32>
+    {
+        let mut state = CameraState
+        {
+            position: Point3::new(0.0, -10.0, 1.0),
+            rotation: Quaternion::from(Euler::new(Rad(-FRAC_PI_2), Rad(0.0), Rad(0.0))),
+            velocity: Vector3::zero(),
+        };
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let mut input = CameraInput::new();
+        input.force = Vector3::new(1.0, 0.5, 1.0);
+        let dt = Duration::from_secs_f64(1.0 / fps as f64);
+        for _ in 0..fps
+        {
+            controller.update(&mut state, &mut projection, &input, dt);
+            input.reset();
+        }
+        state.position
+    }
+
+    #[test]
+    fn motion_is_frame_rate_independent()
+    {
+        let controllers: [fn() -> Box<dyn CameraController>; 3] = [
+            || Box::new(FpsController::new(10.0, 0.2)),
+            || Box::new(OrbitController::new(Point3::new(0.0, 0.0, 0.0), 10.0, 0.2)),
+            || Box::new(PanZoomController::new(10.0)),
+        ];
+        for make in controllers
+        {
+            let slow = simulate(make().as_mut(), 30);
+            let fast = simulate(make().as_mut(), 240);
+            assert!((slow - fast).magnitude() < 1e-3, "{}: {:?} != {:?}", make().name(), slow, fast);
+        }
+    }
+
+    #[test]
+    fn arcball_stays_upright_past_the_pole()
+    {
+        let target = Point3::new(0.0, 0.0, 0.0);
+        let position = Point3::new(0.0, -10.0, 0.0);
+        let mut state = CameraState { position, rotation: look_rotation(position, target), velocity: Vector3::zero() };
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        let mut controller = ArcballController::new(target, 1.0);
+        let mut input = CameraInput::new();
+        input.dragging = true;
+        // drag down over and over, far enough to go over the top several times
+        for i in 0..40
+        {
+            input.mouse_pos = PhysicalPosition::new(0.5, if i % 2 == 0 { 0.2 } else { 0.8 });
+            if i % 2 == 0 { controller.last = None; }
+            controller.update(&mut state, &mut projection, &input, Duration::from_millis(16));
+            let (look, right, up) = screen_axes(state.rotation);
+            let offset = (state.position - target).normalize();
+            assert!(offset.z.abs() <= ARCBALL_MAX_PITCH.sin() + 1e-4, "{:?}", offset);
+            assert!(right.z.abs() < 1e-4 && up.z >= 0.0, "rolled {:?} {:?}", right, up);
+            assert!(look.dot(-offset) > 0.999, "{i} {:?} {:?}", look, offset);
+        }
+    }
+
+    #[test]
+    fn screen_axes_looking_straight_down()
+    {
+        let rotation = look_rotation(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0));
+        let (look, right, up) = screen_axes(rotation);
+        for axis in [look, right, up]
+        {
+            assert!(axis.x.is_finite() && axis.y.is_finite() && axis.z.is_finite());
+            assert!((axis.magnitude() - 1.0).abs() < 1e-4);
+        }
+        assert!(look.dot(right).abs() < 1e-4 && look.dot(up).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,458 @@
+use crate::core::model::{Mesh, Instances};
+use crate::core::renderer::DepthMode;
+use crate::core::bounds::Frustum;
+
+use cgmath::Matrix4;
+
+
+
+
+// LAYOUTS {{{
+// r32float is not filterable, so the layouts can not be derived from the shaders
+fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry
+{
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+
+fn storage_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry
+{
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::R32Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+
+fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry
+{
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+
+fn storage_buffer_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry
+{
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+
+fn compute_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    groups: &[&[wgpu::BindGroupLayoutEntry]],) -> wgpu::ComputePipeline
+{
+    let layouts = groups.iter()
+        .map(|entries| device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries,
+            label: Some(entry_point),
+        }))
+        .collect::<Vec<_>>();
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(entry_point),
+        bind_group_layouts: &layouts.iter().collect::<Vec<_>>(),
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&layout),
+        module: shader,
+        entry_point,
+    })
+}
+// }}}
+
+
+
+
+// HI-Z {{{
+// Depth pyramid of the previous frame, the culling pass treats anything behind it as hidden.
+// One frame late, so fast camera motion can briefly drop objects coming into view.
+pub struct HiZ
+{
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub levels: u32,
+    pub size: (u32, u32),
+    copy_pipeline: wgpu::ComputePipeline,
+    down_pipeline: wgpu::ComputePipeline,
+    params: wgpu::Buffer,
+    // one per level, level 0 reads the depth texture
+    bind_groups: Vec<(wgpu::BindGroup, Option<wgpu::BindGroup>)>,
+    // the view projection the pyramid was built with, None until the first build
+    view_proj: Option<Matrix4<f32>>,
+    depth: DepthMode,
+    // bumped on every resize, so bind groups holding the old view can tell
+    version: u32,
+}
+
+
+impl HiZ
+{
+    pub fn new(device: &wgpu::Device, depth_view: &wgpu::TextureView, width: u32, height: u32, depth: DepthMode) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/hiz.wgsl"));
+        let copy_pipeline = compute_pipeline(device, &shader, "cs_copy", &[
+            &[texture_entry(0), storage_texture_entry(1)],
+        ]);
+        let down_pipeline = compute_pipeline(device, &shader, "cs_down", &[
+            &[storage_texture_entry(1), uniform_entry(2)],
+            &[texture_entry(0)],
+        ]);
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("HiZ Params Buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        params.slice(..).get_mapped_range_mut()[..4].copy_from_slice(bytemuck::bytes_of(&((depth == DepthMode::ReverseZ) as u32)));
+        params.unmap();
+
+        let (texture, view, levels, bind_groups) = Self::create_pyramid(device, &copy_pipeline, &down_pipeline, &params, depth_view, width, height);
+        Self
+        {
+            texture,
+            view,
+            levels,
+            size: (width, height),
+            copy_pipeline,
+            down_pipeline,
+            params,
+            bind_groups,
+            view_proj: None,
+            depth,
+            version: 0,
+        }
+    }
+
+
+    #[allow(clippy::type_complexity)]
+    fn create_pyramid(
+        device: &wgpu::Device,
+        copy_pipeline: &wgpu::ComputePipeline,
+        down_pipeline: &wgpu::ComputePipeline,
+        params: &wgpu::Buffer,
+        depth_view: &wgpu::TextureView,
+        width: u32,
+        height: u32,) -> (wgpu::Texture, wgpu::TextureView, u32, Vec<(wgpu::BindGroup, Option<wgpu::BindGroup>)>)
+    {
+        let levels = 32 - width.max(height).max(1).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("hiz_texture"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: levels,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mip = |level: u32| texture.create_view(&wgpu::TextureViewDescriptor {
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+
+        let bind_groups = (0..levels).map(|level| {
+            let dst = mip(level);
+            if level == 0
+            {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &copy_pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(depth_view) },
+                        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&dst) },
+                    ],
+                    label: Some("hiz_copy_bind_group"),
+                });
+                return (bind_group, None);
+            }
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &down_pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&dst) },
+                    wgpu::BindGroupEntry { binding: 2, resource: params.as_entire_binding() },
+                ],
+                label: Some("hiz_down_bind_group"),
+            });
+            let src = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &down_pipeline.get_bind_group_layout(1),
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&mip(level - 1)) },
+                ],
+                label: Some("hiz_src_bind_group"),
+            });
+            (bind_group, Some(src))
+        }).collect();
+
+        (texture, view, levels, bind_groups)
+    }
+
+
+    pub fn resize(&mut self, device: &wgpu::Device, depth_view: &wgpu::TextureView, width: u32, height: u32)
+    {
+        (self.texture, self.view, self.levels, self.bind_groups) =
+            Self::create_pyramid(device, &self.copy_pipeline, &self.down_pipeline, &self.params, depth_view, width, height);
+        self.size = (width, height);
+        self.view_proj = None;
+        self.version += 1;
+    }
+
+
+    // call after the depth texture has been rendered with view_proj
+    pub fn build(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: Matrix4<f32>)
+    {
+        for (level, (bind_group, src)) in self.bind_groups.iter().enumerate()
+        {
+            let width = (self.size.0 >> level).max(1);
+            let height = (self.size.1 >> level).max(1);
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("HiZ Pass"),
+                timestamp_writes: None,
+            });
+            match src
+            {
+                None => compute_pass.set_pipeline(&self.copy_pipeline),
+                Some(src) =>
+                {
+                    compute_pass.set_pipeline(&self.down_pipeline);
+                    compute_pass.set_bind_group(1, src, &[]);
+                }
+            }
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(width.div_ceil(8), height.div_ceil(8), 1);
+        }
+        self.view_proj = Some(view_proj);
+    }
+}
+// }}}
+
+
+
+
+// CULLING {{{
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams
+{
+    hiz_view_proj: [[f32; 4]; 4],
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    count: u32,
+    plane_count: u32,
+    hiz_levels: u32,
+    reverse_z: u32,
+    hiz_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+
+// output of one culling dispatch, bound as instance and indirect buffers when drawing
+pub struct CullTarget
+{
+    pub instances: wgpu::Buffer,
+    pub indirect: wgpu::Buffer,
+    params: wgpu::Buffer,
+    capacity: usize,
+    // bind groups of the last dispatch with the input buffer id and hi-z version they were made for
+    bind_groups: Option<((u64, Option<u32>), wgpu::BindGroup, wgpu::BindGroup)>,
+}
+
+
+pub struct GpuCuller
+{
+    pipeline: wgpu::ComputePipeline,
+    // bound when hi-z is off, the shader never reads it then
+    no_hiz: wgpu::TextureView,
+}
+
+
+impl GpuCuller
+{
+    pub fn new(device: &wgpu::Device) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/cull.wgsl"));
+        let pipeline = compute_pipeline(device, &shader, "cs_main", &[
+            &[uniform_entry(0), storage_buffer_entry(1, true), storage_buffer_entry(2, false), storage_buffer_entry(3, false)],
+            &[texture_entry(0)],
+        ]);
+        let no_hiz = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("no_hiz_texture"),
+            size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { pipeline, no_hiz }
+    }
+
+
+    pub fn target(&self, device: &wgpu::Device) -> CullTarget
+    {
+        CullTarget
+        {
+            instances: Self::create_output(device, 1),
+            indirect: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cull Indirect Buffer"),
+                size: std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            params: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cull Params Buffer"),
+                size: std::mem::size_of::<CullParams>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            capacity: 1,
+            bind_groups: None,
+        }
+    }
+
+
+    fn create_output(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer
+    {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Instance Buffer"),
+            size: (capacity * std::mem::size_of::<[[f32; 4]; 4]>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+
+    // records the culling dispatch for one mesh, hiz is skipped until it has been built once
+    #[allow(clippy::too_many_arguments)]
+    pub fn cull(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &mut CullTarget,
+        mesh: &Mesh,
+        instances: &Instances,
+        frustum: &Frustum,
+        hiz: Option<&HiZ>,)
+    {
+        let args = wgpu::util::DrawIndexedIndirect {
+            vertex_count: mesh.num_elements,
+            instance_count: 0,
+            base_index: 0,
+            vertex_offset: 0,
+            base_instance: 0,
+        };
+        queue.write_buffer(&target.indirect, 0, args.as_bytes());
+        // an empty instance buffer is too small to bind
+        if instances.is_empty() { return; }
+
+        if instances.len() > target.capacity
+        {
+            target.capacity = instances.len().max(target.capacity * 2);
+            target.instances = Self::create_output(device, target.capacity);
+            target.bind_groups = None;
+        }
+
+        let hiz = hiz.and_then(|hiz| hiz.view_proj.map(|view_proj| (hiz, view_proj)));
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, p) in planes.iter_mut().zip(&frustum.planes)
+        {
+            *plane = (*p).into();
+        }
+        let sphere = &mesh.bounding_sphere;
+        let params = CullParams
+        {
+            hiz_view_proj: hiz.map_or([[0.0; 4]; 4], |(_, view_proj)| view_proj.into()),
+            planes,
+            sphere: [sphere.center.x, sphere.center.y, sphere.center.z, sphere.radius],
+            count: instances.len() as u32,
+            plane_count: frustum.planes.len().min(6) as u32,
+            hiz_levels: hiz.map_or(0, |(hiz, _)| hiz.levels),
+            reverse_z: hiz.is_some_and(|(hiz, _)| hiz.depth == DepthMode::ReverseZ) as u32,
+            hiz_size: hiz.map_or([1.0; 2], |(hiz, _)| [hiz.size.0 as f32, hiz.size.1 as f32]),
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&target.params, 0, bytemuck::cast_slice(&[params]));
+
+        let key = (instances.buffer_id(), hiz.map(|(hiz, _)| hiz.version));
+        if target.bind_groups.as_ref().map(|(k, _, _)| *k) != Some(key)
+        {
+            let (bind_group, hiz_bind_group) = self.create_bind_groups(device, target, instances, hiz.map(|(hiz, _)| hiz));
+            target.bind_groups = Some((key, bind_group, hiz_bind_group));
+        }
+        let Some((_, bind_group, hiz_bind_group)) = &target.bind_groups else { return };
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.set_bind_group(1, hiz_bind_group, &[]);
+        compute_pass.dispatch_workgroups((instances.len() as u32).div_ceil(64), 1, 1);
+    }
+
+
+    fn create_bind_groups(&self, device: &wgpu::Device, target: &CullTarget, instances: &Instances, hiz: Option<&HiZ>) -> (wgpu::BindGroup, wgpu::BindGroup)
+    {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: target.params.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: instances.buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: target.instances.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: target.indirect.as_entire_binding() },
+            ],
+            label: Some("cull_bind_group"),
+        });
+        let hiz_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.pipeline.get_bind_group_layout(1),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hiz.map_or(&self.no_hiz, |hiz| &hiz.view)),
+                },
+            ],
+            label: Some("cull_hiz_bind_group"),
+        });
+        (bind_group, hiz_bind_group)
+    }
+}
+// }}}
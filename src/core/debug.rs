@@ -0,0 +1,354 @@
+use crate::core::bounds::Aabb;
+use crate::core::model::Mesh;
+use crate::core::renderer::DepthMode;
+use crate::core::texture::Texture;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Point3, Vector3, Vector4};
+
+
+
+
+// LINES {{{
+pub const RED: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+pub const GREEN: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+pub const BLUE: [f32; 4] = [0.3, 0.4, 1.0, 1.0];
+pub const YELLOW: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+pub const GRAY: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex
+{
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+
+// pairs of vertices in world space, one line each
+#[derive(Debug, Default)]
+pub struct DebugLines
+{
+    pub vertices: Vec<DebugVertex>,
+}
+
+
+impl DebugLines
+{
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 4])
+    {
+        self.vertices.push(DebugVertex { position: a.into(), color });
+        self.vertices.push(DebugVertex { position: b.into(), color });
+    }
+
+
+    // the twelve edges of eight corners ordered by their x, y, z bits
+    fn corners(&mut self, corners: [Point3<f32>; 8], color: [f32; 4])
+    {
+        for (a, b) in [(0, 1), (2, 3), (4, 5), (6, 7), (0, 2), (1, 3), (4, 6), (5, 7), (0, 4), (1, 5), (2, 6), (3, 7)]
+        {
+            self.line(corners[a], corners[b], color);
+        }
+    }
+
+
+    // the box as transform places it, identity for a world space box
+    pub fn aabb(&mut self, aabb: &Aabb, transform: &Matrix4<f32>, color: [f32; 4])
+    {
+        let corners = std::array::from_fn(|i|
+        {
+            let x = if i & 1 == 0 { aabb.min.x } else { aabb.max.x };
+            let y = if i & 2 == 0 { aabb.min.y } else { aabb.max.y };
+            let z = if i & 4 == 0 { aabb.min.z } else { aabb.max.z };
+            transform.transform_point(Point3::new(x, y, z))
+        });
+        self.corners(corners, color);
+    }
+
+
+    // three great circles
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4])
+    {
+        for (u, v) in [(Vector3::unit_x(), Vector3::unit_y()), (Vector3::unit_y(), Vector3::unit_z()), (Vector3::unit_z(), Vector3::unit_x())]
+        {
+            let point = |i: usize|
+            {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + (u * angle.cos() + v * angle.sin()) * radius
+            };
+            for i in 0..CIRCLE_SEGMENTS
+            {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+
+    // a small three axis cross, for positions like lights
+    pub fn point(&mut self, position: Point3<f32>, size: f32, color: [f32; 4])
+    {
+        for axis in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+        {
+            self.line(position - axis * size, position + axis * size, color);
+        }
+    }
+
+
+    // x red, y green, z blue from the transform's origin
+    pub fn axes(&mut self, transform: &Matrix4<f32>, length: f32)
+    {
+        let origin = transform.transform_point(Point3::origin());
+        for (axis, color) in [(Vector3::unit_x(), RED), (Vector3::unit_y(), GREEN), (Vector3::unit_z(), BLUE)]
+        {
+            self.line(origin, transform.transform_point(Point3::from_vec(axis * length)), color);
+        }
+    }
+
+
+    // the near plane of view_proj and its sides running length units past it. a reverse z
+    // projection has its far plane at infinity, so the sides are drawn to a length instead
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, depth: DepthMode, length: f32, color: [f32; 4])
+    {
+        let Some(inverse) = view_proj.invert() else { return };
+        let unproject = |x: f32, y: f32, z: f32|
+        {
+            let p = inverse * Vector4::new(x, y, z, 1.0);
+            Point3::from_homogeneous(p)
+        };
+        let near = depth.near_value();
+        let between = (near + depth.clear_value()) / 2.0;
+        let mut corners = [Point3::origin(); 8];
+        for i in 0..4
+        {
+            let x = if i & 1 == 0 { -1.0 } else { 1.0 };
+            let y = if i & 2 == 0 { -1.0 } else { 1.0 };
+            let a = unproject(x, y, near);
+            let b = unproject(x, y, between);
+            corners[i] = a;
+            corners[i + 4] = a + (b - a).normalize() * length;
+        }
+        self.corners(corners, color);
+    }
+
+
+    // square lines on the z-up ground plane through center, half_size out in x and y
+    pub fn grid(&mut self, center: Point3<f32>, half_size: f32, spacing: f32, color: [f32; 4])
+    {
+        let steps = (half_size / spacing).floor() as i32;
+        for i in -steps..=steps
+        {
+            let offset = i as f32 * spacing;
+            self.line(center + Vector3::new(offset, -half_size, 0.0), center + Vector3::new(offset, half_size, 0.0), color);
+            self.line(center + Vector3::new(-half_size, offset, 0.0), center + Vector3::new(half_size, offset, 0.0), color);
+        }
+    }
+
+
+    // vertex normals as transform places the mesh
+    pub fn normals(&mut self, mesh: &Mesh, transform: &Matrix4<f32>, length: f32, color: [f32; 4])
+    {
+        let m = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+        // normals stay perpendicular under non-uniform scale with the inverse transpose
+        let Some(normal_matrix) = m.invert().map(|m| m.transpose()) else { return };
+        for (position, normal) in mesh.positions.iter().zip(&mesh.normals)
+        {
+            let normal = normal_matrix * Vector3::from(*normal);
+            if normal.magnitude2() == 0.0 { continue; }
+            let start = transform.transform_point(Point3::from(*position));
+            self.line(start, start + normal.normalize() * length, color);
+        }
+    }
+
+
+    pub fn len(&self) -> usize { self.vertices.len() / 2 }
+    pub fn is_empty(&self) -> bool { self.vertices.is_empty() }
+    pub fn clear(&mut self) { self.vertices.clear(); }
+}
+// }}}
+
+
+
+
+// DEBUG DRAW {{{
+// lines gathered during update and drawn into the scene's pass by every render until the next
+// update clears them. world lines are hidden behind geometry, overlay lines are always on top.
+// neither writes depth, so culling never sees them
+pub struct DebugDraw
+{
+    pub world: DebugLines,
+    pub overlay: DebugLines,
+    pub visible: bool,
+    world_pipeline: wgpu::RenderPipeline,
+    overlay_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    // vertices the buffer holds
+    capacity: usize,
+}
+
+
+impl DebugDraw
+{
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, camera_layout: &wgpu::BindGroupLayout, depth: DepthMode) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/debug.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("debug_pipeline_layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, depth_compare| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        // lines lying on a surface pass along with it
+        let compare = match depth
+        {
+            DepthMode::Standard => wgpu::CompareFunction::LessEqual,
+            DepthMode::ReverseZ => wgpu::CompareFunction::GreaterEqual,
+        };
+        let world_pipeline = pipeline("debug_world_pipeline", compare);
+        let overlay_pipeline = pipeline("debug_overlay_pipeline", wgpu::CompareFunction::Always);
+
+        let capacity = 4096;
+        Self
+        {
+            world: DebugLines::default(),
+            overlay: DebugLines::default(),
+            visible: false,
+            world_pipeline,
+            overlay_pipeline,
+            vertex_buffer: Self::buffer(device, capacity),
+            capacity,
+        }
+    }
+
+
+    fn buffer(device: &wgpu::Device, vertices: usize) -> wgpu::Buffer
+    {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("debug_vertex_buffer"),
+            size: (vertices * std::mem::size_of::<DebugVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+
+    pub fn clear(&mut self)
+    {
+        self.world.clear();
+        self.overlay.clear();
+    }
+
+
+    // uploads both lists before the pass they are drawn in
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue)
+    {
+        if !self.visible { return; }
+        let count = self.world.vertices.len() + self.overlay.vertices.len();
+        if count > self.capacity
+        {
+            self.capacity = count.next_power_of_two();
+            self.vertex_buffer = Self::buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.world.vertices));
+        let offset = (self.world.vertices.len() * std::mem::size_of::<DebugVertex>()) as u64;
+        queue.write_buffer(&self.vertex_buffer, offset, bytemuck::cast_slice(&self.overlay.vertices));
+    }
+
+
+    // the pass needs a depth attachment in the mode the draw was made for
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, camera_bind_group: &'a wgpu::BindGroup)
+    {
+        if !self.visible { return; }
+        let world = self.world.vertices.len() as u32;
+        let overlay = self.overlay.vertices.len() as u32;
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        if world > 0
+        {
+            render_pass.set_pipeline(&self.world_pipeline);
+            render_pass.draw(0..world, 0..1);
+        }
+        if overlay > 0
+        {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(world..world + overlay, 0..1);
+        }
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::core::camera::{look_rotation, CameraState, Projection};
+    use cgmath::Deg;
+
+    #[test]
+    fn frustum_starts_on_the_near_plane_and_opens_along_the_view()
+    {
+        let eye = Point3::new(0.0, -10.0, 1.0);
+        let target = Point3::new(0.0, 0.0, 1.0);
+        let state = CameraState { position: eye, rotation: look_rotation(eye, target), velocity: Vector3::zero() };
+        for depth in [DepthMode::Standard, DepthMode::ReverseZ]
+        {
+            let mut projection = Projection::new(800, 600, Deg(60.0), 0.5, 100.0);
+            projection.set_depth_mode(depth);
+            let view = Matrix4::look_to_rh(eye, state.rotation * Vector3::unit_z(), Vector3::unit_z());
+            let mut lines = DebugLines::default();
+            lines.frustum(projection.calc_matrix() * view, depth, 5.0, GRAY);
+            assert_eq!(lines.len(), 12);
+
+            let look = (target - eye).normalize();
+            let corners: Vec<Point3<f32>> = lines.vertices.iter().map(|v| Point3::from(v.position)).collect();
+            // the last four lines run from the near corners out along the sides
+            for pair in corners[16..].chunks(2)
+            {
+                assert!(((pair[0] - eye).dot(look) - 0.5).abs() < 1e-3, "{:?} near corner off the plane", depth);
+                let side = pair[1] - pair[0];
+                assert!((side.magnitude() - 5.0).abs() < 1e-3);
+                assert!(side.normalize().dot(look) > 0.5, "{:?} side leaves the view", depth);
+                assert!((pair[0] - eye).normalize().dot(side.normalize()) > 0.999, "{:?} side misses the eye", depth);
+            }
+        }
+    }
+}
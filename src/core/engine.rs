@@ -1,5 +1,6 @@
 use winit::window::Window;
 use winit::event::WindowEvent;
+use instant::Duration;
 
 pub trait Engine
 {
@@ -13,6 +14,51 @@ pub trait Engine
 }
 
 
+// turns the time between redraws into whole simulation ticks of a fixed length
+#[derive(Debug)]
+pub struct FixedTimestep
+{
+    pub step: Duration,
+    // the most ticks one redraw may run, past that the simulation slows down instead of
+    // falling further behind with every slow frame
+    pub max_steps: u32,
+    accumulator: Duration,
+}
+
+
+impl FixedTimestep
+{
+    pub fn new(tick_rate: u32, max_steps: u32) -> Self
+    {
+        Self { step: Duration::from_secs(1) / tick_rate.max(1), max_steps, accumulator: Duration::ZERO }
+    }
+
+    // how many ticks to run for dt of real time
+    pub fn advance(&mut self, dt: Duration) -> u32
+    {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps
+        {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if self.accumulator >= self.step
+        {
+            let behind = self.accumulator.as_nanos() % self.step.as_nanos();
+            self.accumulator = Duration::from_nanos(behind as u64);
+        }
+        steps
+    }
+
+    // how far along the next tick the time is, for blending the last two ticks when rendering
+    pub fn alpha(&self) -> f32
+    {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+
 pub struct WindowState
 {
     pub size: winit::dpi::PhysicalSize<u32>,
@@ -43,7 +89,8 @@ macro_rules! new_device {
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor 
             {
-                features: wgpu::Features::empty(),
+                // timestamps are optional, GpuTimer does nothing without them
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                 limits: if cfg!(target_arch = "wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
@@ -80,7 +127,8 @@ macro_rules! new_device {
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor 
             {
-                features: wgpu::Features::empty(),
+                // timestamps are optional, GpuTimer does nothing without them
+                features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                 limits: if cfg!(target_arch = "wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
@@ -111,3 +159,47 @@ macro_rules! new_device {
         (device, queue, window_state)
     }};
 }
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn whole_steps_run_and_the_rest_carries_over()
+    {
+        let mut timestep = FixedTimestep::new(100, 5);
+        assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-4);
+        // the 5ms left over and 5ms more make a third step
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+        assert!(timestep.alpha().abs() < 1e-4);
+        assert_eq!(timestep.advance(Duration::from_millis(3)), 0);
+        assert!((timestep.alpha() - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    fn max_steps_drops_the_excess()
+    {
+        let mut timestep = FixedTimestep::new(100, 5);
+        // a one second hitch runs five steps and keeps only the part of a step it was into
+        assert_eq!(timestep.advance(Duration::from_millis(1004)), 5);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-4, "{}", timestep.alpha());
+        assert_eq!(timestep.advance(Duration::from_millis(10)), 1);
+    }
+
+    #[test]
+    fn alpha_stays_below_one()
+    {
+        let mut timestep = FixedTimestep::new(60, 3);
+        for ms in [1, 7, 16, 17, 33, 90, 250, 3, 1000, 0, 16]
+        {
+            timestep.advance(Duration::from_millis(ms));
+            let alpha = timestep.alpha();
+            assert!((0.0..1.0).contains(&alpha), "{}ms: {}", ms, alpha);
+        }
+    }
+}
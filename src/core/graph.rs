@@ -0,0 +1,214 @@
+use crate::core::model::{Instance, Instances};
+use crate::core::renderer::InstanceRaw;
+
+use std::collections::HashMap;
+use cgmath::{Matrix4, SquareMatrix};
+
+
+
+
+// NODE {{{
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct NodeId(pub usize);
+
+
+pub struct Node
+{
+    pub name: String,
+    pub model: Option<String>,
+    // index among the model's instances
+    slot: usize,
+    local: Instance,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: Matrix4<f32>,
+    dirty: bool,
+}
+
+
+impl Node
+{
+    pub fn local(&self) -> &Instance { &self.local }
+    pub fn parent(&self) -> Option<NodeId> { self.parent }
+    pub fn children(&self) -> &[NodeId] { &self.children }
+    // only valid after SceneGraph::update
+    pub fn world(&self) -> Matrix4<f32> { self.world }
+}
+// }}}
+
+
+
+
+// GRAPH {{{
+pub struct SceneGraph
+{
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+    // nodes drawn with each model, in insertion order
+    by_model: HashMap<String, Vec<NodeId>>,
+    // instance slots per model whose world matrices changed in the last update
+    changed: HashMap<String, Vec<usize>>,
+}
+
+
+impl SceneGraph
+{
+    pub fn new() -> Self
+    {
+        Self
+        {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            by_model: HashMap::new(),
+            changed: HashMap::new(),
+        }
+    }
+
+
+    pub fn add(&mut self, name: &str, model: Option<&str>, local: Instance, parent: Option<NodeId>) -> NodeId
+    {
+        let id = NodeId(self.nodes.len());
+        let slot = model.map_or(0, |m| self.by_model.get(m).map_or(0, Vec::len));
+        self.nodes.push(Node
+        {
+            name: name.to_string(),
+            model: model.map(str::to_string),
+            slot,
+            local,
+            parent,
+            children: Vec::new(),
+            world: Matrix4::identity(),
+            dirty: true,
+        });
+        match parent
+        {
+            Some(p) => self.nodes[p.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        if let Some(model) = model
+        {
+            self.by_model.entry(model.to_string()).or_default().push(id);
+        }
+        id
+    }
+
+
+    pub fn node(&self, id: NodeId) -> &Node { &self.nodes[id.0] }
+    pub fn len(&self) -> usize { self.nodes.len() }
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    pub fn find(&self, name: &str) -> Option<NodeId>
+    {
+        self.nodes.iter().position(|n| n.name == name).map(NodeId)
+    }
+
+
+    // marks the node dirty, its children follow in the next update
+    pub fn local_mut(&mut self, id: NodeId) -> &mut Instance
+    {
+        let node = &mut self.nodes[id.0];
+        node.dirty = true;
+        &mut node.local
+    }
+
+
+    // keeps the local transform, so the node moves with its new parent
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>)
+    {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor
+        {
+            assert!(a != id, "scene graph: node {:?} can not be its own ancestor", self.nodes[id.0].name);
+            ancestor = self.nodes[a.0].parent;
+        }
+
+        match self.nodes[id.0].parent
+        {
+            Some(p) => self.nodes[p.0].children.retain(|&c| c != id),
+            None => self.roots.retain(|&c| c != id),
+        }
+        match parent
+        {
+            Some(p) => self.nodes[p.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.nodes[id.0].parent = parent;
+        self.nodes[id.0].dirty = true;
+    }
+
+
+    // recomputes the world matrix of every dirty node and everything below it
+    pub fn update(&mut self)
+    {
+        self.changed.clear();
+        let mut stack : Vec<(NodeId, Matrix4<f32>, bool)> = self.roots.iter()
+            .map(|&id| (id, Matrix4::identity(), false))
+            .collect();
+
+        while let Some((id, parent_world, parent_dirty)) = stack.pop()
+        {
+            let node = &mut self.nodes[id.0];
+            let dirty = node.dirty || parent_dirty;
+            if dirty
+            {
+                node.world = parent_world * node.local.to_matrix();
+                node.dirty = false;
+                if let Some(model) = &node.model
+                {
+                    self.changed.entry(model.clone()).or_default().push(node.slot);
+                }
+            }
+            let world = node.world;
+            stack.extend(node.children.iter().map(|&c| (c, world, dirty)));
+        }
+    }
+
+
+    pub fn is_changed(&self, model: &str) -> bool { self.changed.contains_key(model) }
+
+
+    pub fn pack(&self, model: &str) -> Vec<InstanceRaw>
+    {
+        self.by_model.get(model)
+            .map(|ids| ids.iter().map(|id| InstanceRaw { model: self.nodes[id.0].world.into() }).collect())
+            .unwrap_or_default()
+    }
+
+
+    fn world_instance(&self, id: NodeId) -> Instance
+    {
+        Instance::from_matrix(self.nodes[id.0].world)
+    }
+
+
+    pub fn create_instances(&self, device: &wgpu::Device, model: &str) -> Instances
+    {
+        let ids = self.by_model.get(model).map(Vec::as_slice).unwrap_or_default();
+        Instances::new(device, ids.iter().map(|&id| self.world_instance(id)).collect())
+    }
+
+
+    // copies the world transforms touched by the last update and uploads them
+    pub fn upload(&self, device: &wgpu::Device, queue: &wgpu::Queue, model: &str, instances: &mut Instances)
+    {
+        let Some(slots) = self.changed.get(model) else { return };
+        let ids = &self.by_model[model];
+        instances.truncate(ids.len());
+        while instances.len() < ids.len()
+        {
+            instances.push(self.world_instance(ids[instances.len()]));
+        }
+        for &slot in slots
+        {
+            instances.set(slot, self.world_instance(ids[slot]));
+        }
+        instances.upload(device, queue);
+    }
+}
+
+
+impl Default for SceneGraph
+{
+    fn default() -> Self { Self::new() }
+}
+// }}}
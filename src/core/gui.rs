@@ -0,0 +1,530 @@
+use crate::core::camera::Camera;
+use crate::core::graph::{NodeId, SceneGraph};
+use crate::core::model::{Color, Material};
+
+use cgmath::{Deg, Euler, Quaternion, Rad};
+use egui::epaint::{ClippedPrimitive, ImageData, ImageDelta, Primitive, Vertex};
+use egui::{TextureId, TexturesDelta};
+use std::collections::HashMap;
+use winit::event::{ElementState, WindowEvent};
+use winit::window::Window;
+
+
+
+
+// GUI {{{
+// egui drawn over the frame, a frame of ui is built between begin and end and drawn by render.
+// egui-wgpu is left out on purpose: the release that goes with egui-winit 0.22 (the one for winit 0.28)
+// is built on wgpu 0.16, and 0.26, the first one this tree resolves after it, wants wgpu 0.19 and
+// winit 0.29. egui 0.24 has an egui-wgpu on wgpu 0.18 but also brings the viewport rework of
+// egui-winit, until that move GuiRenderer below draws egui's meshes itself.
+pub struct Gui
+{
+    pub context: egui::Context,
+    pub visible: bool,
+    state: egui_winit::State,
+    renderer: GuiRenderer,
+    primitives: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+    screen: Screen,
+}
+
+
+impl Gui
+{
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, window: &Window) -> Self
+    {
+        let mut state = egui_winit::State::new(window);
+        state.set_pixels_per_point(window.scale_factor() as f32);
+        state.set_max_texture_side(device.limits().max_texture_dimension_2d as usize);
+        let size = window.inner_size();
+        let screen = Screen { width: size.width, height: size.height, pixels_per_point: state.pixels_per_point() };
+        Self
+        {
+            context: egui::Context::default(),
+            visible: false,
+            state,
+            renderer: GuiRenderer::new(device, format),
+            primitives: Vec::new(),
+            textures: TexturesDelta::default(),
+            screen,
+        }
+    }
+
+
+    // hands the event to egui while visible, true when egui keeps it from the app. egui sees every
+    // event but only takes the keyboard while a widget has focus and the mouse while it is over or
+    // dragging a window. releases always go through so nothing stays held in the app
+    pub fn window_event(&mut self, event: &WindowEvent) -> bool
+    {
+        if !self.visible { return false; }
+        let response = self.state.on_event(&self.context, event);
+        response.consumed && match event
+        {
+            WindowEvent::KeyboardInput { input, .. } =>
+                input.state == ElementState::Pressed && self.context.wants_keyboard_input(),
+            WindowEvent::ReceivedCharacter(_) => self.context.wants_keyboard_input(),
+            WindowEvent::MouseInput { state, .. } =>
+                *state == ElementState::Pressed && self.context.wants_pointer_input(),
+            WindowEvent::MouseWheel { .. } => self.context.wants_pointer_input(),
+            _ => false,
+        }
+    }
+
+
+    // the returned context builds this frame's windows until end
+    pub fn begin(&mut self, window: &Window) -> egui::Context
+    {
+        let input = self.state.take_egui_input(window);
+        self.context.begin_frame(input);
+        self.context.clone()
+    }
+
+
+    pub fn end(&mut self, window: &Window)
+    {
+        let output = self.context.end_frame();
+        self.state.handle_platform_output(window, &self.context, output.platform_output);
+        self.primitives = self.context.tessellate(output.shapes);
+        self.textures.append(output.textures_delta);
+        let size = window.inner_size();
+        self.screen = Screen { width: size.width, height: size.height, pixels_per_point: self.context.pixels_per_point() };
+    }
+
+
+    // draws the last ended frame over view, nothing once it has been drawn
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView)
+    {
+        let textures = std::mem::take(&mut self.textures);
+        for (id, delta) in &textures.set
+        {
+            self.renderer.set_texture(device, queue, *id, delta);
+        }
+        let primitives = std::mem::take(&mut self.primitives);
+        self.renderer.render(device, queue, encoder, view, &primitives, self.screen);
+        for id in &textures.free
+        {
+            self.renderer.textures.remove(id);
+        }
+    }
+}
+// }}}
+
+
+
+
+// RENDERER {{{
+// the target in pixels and how many pixels make a point
+#[derive(Debug, Clone, Copy)]
+struct Screen
+{
+    width: u32,
+    height: u32,
+    pixels_per_point: f32,
+}
+
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GuiScreen
+{
+    // in points
+    size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+
+// one draw call per egui mesh, clipped with a scissor rect and bound to its texture
+struct GuiRenderer
+{
+    pipeline: wgpu::RenderPipeline,
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    textures: HashMap<TextureId, (wgpu::Texture, wgpu::BindGroup)>,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // vertices and indices the buffers hold
+    capacity: (usize, usize),
+}
+
+
+impl GuiRenderer
+{
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/gui.wgsl"));
+        let screen_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gui_screen"),
+            size: std::mem::size_of::<GuiScreen>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let screen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("gui_screen_layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("gui_screen_bind_group"),
+            layout: &screen_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: screen_buffer.as_entire_binding() }],
+        });
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("gui_texture_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("gui_pipeline_layout"),
+            bind_group_layouts: &[&screen_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("gui_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Unorm8x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                // egui colors are gamma encoded, an srgb target wants them linear
+                entry_point: if format.is_srgb() { "fs_linear" } else { "fs_gamma" },
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::OneMinusDstAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let capacity = (1024, 2048);
+        let (vertex_buffer, index_buffer) = Self::buffers(device, capacity);
+        Self
+        {
+            pipeline,
+            screen_buffer,
+            screen_bind_group,
+            texture_layout,
+            sampler,
+            textures: HashMap::new(),
+            vertex_buffer,
+            index_buffer,
+            capacity,
+        }
+    }
+
+
+    fn buffers(device: &wgpu::Device, (vertices, indices): (usize, usize)) -> (wgpu::Buffer, wgpu::Buffer)
+    {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gui_vertex_buffer"),
+            size: (vertices * std::mem::size_of::<Vertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gui_index_buffer"),
+            size: (indices * std::mem::size_of::<u32>()) as u64,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (vertex_buffer, index_buffer)
+    }
+
+
+    // a whole new texture, or a patch of one egui already made
+    fn set_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, id: TextureId, delta: &ImageDelta)
+    {
+        let pixels: Vec<u8> = match &delta.image
+        {
+            ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+            ImageData::Font(image) => image.srgba_pixels(None).flat_map(|c| c.to_array()).collect(),
+        };
+        let [width, height] = delta.image.size();
+        let size = wgpu::Extent3d { width: width as u32, height: height as u32, depth_or_array_layers: 1 };
+        let origin = match delta.pos
+        {
+            Some([x, y]) => wgpu::Origin3d { x: x as u32, y: y as u32, z: 0 },
+            None =>
+            {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("gui_texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("gui_texture_bind_group"),
+                    layout: &self.texture_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
+                        wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                    ],
+                });
+                self.textures.insert(id, (texture, bind_group));
+                wgpu::Origin3d::ZERO
+            }
+        };
+        let Some((texture, _)) = self.textures.get(&id) else { return };
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width as u32),
+                rows_per_image: Some(height as u32),
+            },
+            size,
+        );
+    }
+
+
+    fn render(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        primitives: &[ClippedPrimitive],
+        Screen { width, height, pixels_per_point }: Screen)
+    {
+        // every mesh goes into the same buffers, drawn from its own index range and base vertex
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut draws = Vec::new();
+        for ClippedPrimitive { clip_rect, primitive } in primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else { continue };
+            let min_x = ((clip_rect.min.x * pixels_per_point).round() as u32).min(width);
+            let min_y = ((clip_rect.min.y * pixels_per_point).round() as u32).min(height);
+            let max_x = ((clip_rect.max.x * pixels_per_point).round() as u32).clamp(min_x, width);
+            let max_y = ((clip_rect.max.y * pixels_per_point).round() as u32).clamp(min_y, height);
+            if max_x == min_x || max_y == min_y || mesh.indices.is_empty() { continue; }
+            let start = indices.len() as u32;
+            draws.push(([min_x, min_y, max_x - min_x, max_y - min_y], mesh.texture_id, start..start + mesh.indices.len() as u32, vertices.len() as i32));
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+        if draws.is_empty() { return; }
+
+        if vertices.len() > self.capacity.0 || indices.len() > self.capacity.1
+        {
+            self.capacity = (vertices.len().next_power_of_two().max(self.capacity.0), indices.len().next_power_of_two().max(self.capacity.1));
+            (self.vertex_buffer, self.index_buffer) = Self::buffers(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+        let screen = GuiScreen { size: [width as f32 / pixels_per_point, height as f32 / pixels_per_point], _padding: [0.0; 2] };
+        queue.write_buffer(&self.screen_buffer, 0, bytemuck::cast_slice(&[screen]));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Gui Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.screen_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for ([x, y, w, h], texture, range, base_vertex) in draws
+        {
+            let Some((_, bind_group)) = self.textures.get(&texture) else { continue };
+            render_pass.set_scissor_rect(x, y, w, h);
+            render_pass.set_bind_group(1, bind_group, &[]);
+            render_pass.draw_indexed(range, base_vertex, 0..1);
+        }
+    }
+}
+// }}}
+
+
+
+
+// PANELS {{{
+fn drag_vector(ui: &mut egui::Ui, v: &mut [f32; 3], speed: f32) -> bool
+{
+    ui.horizontal(|ui|
+    {
+        let mut changed = false;
+        for (axis, value) in ["x", "y", "z"].into_iter().zip(v.iter_mut())
+        {
+            changed |= ui.add(egui::DragValue::new(value).prefix(axis).speed(speed)).changed();
+        }
+        changed
+    }).inner
+}
+
+
+// active controller, position and field of view or zoom
+pub fn camera_panel(ui: &mut egui::Ui, camera: &mut Camera)
+{
+    egui::ComboBox::from_label("controller")
+        .selected_text(camera.controller().name())
+        .show_ui(ui, |ui|
+        {
+            for i in 0..camera.controllers.len()
+            {
+                let name = camera.controllers[i].name();
+                if ui.selectable_label(camera.active == i, name).clicked()
+                {
+                    camera.set_controller(i);
+                }
+            }
+        });
+
+    let mut position: [f32; 3] = camera.state.position.into();
+    ui.label("position");
+    if drag_vector(ui, &mut position, 0.05)
+    {
+        camera.state.position = position.into();
+    }
+    if let Some(fovy) = camera.projection.fovy()
+    {
+        let mut degrees = Deg::from(fovy).0;
+        if ui.add(egui::Slider::new(&mut degrees, 5.0..=120.0).text("fovy")).changed()
+        {
+            camera.projection.set_fovy(Deg(degrees).into());
+        }
+    }
+    if let Some(mut zoom) = camera.projection.zoom()
+    {
+        if ui.add(egui::Slider::new(&mut zoom, 1.0..=64.0).text("zoom")).changed()
+        {
+            camera.projection.set_zoom(zoom);
+        }
+    }
+}
+
+
+// diffuse colors, uploaded as they change
+pub fn material_panel(ui: &mut egui::Ui, queue: &wgpu::Queue, materials: &mut [Material])
+{
+    for (i, material) in materials.iter_mut().enumerate()
+    {
+        ui.horizontal(|ui|
+        {
+            let mut color = material.diffuse_color.color;
+            if ui.color_edit_button_rgba_unmultiplied(&mut color).changed()
+            {
+                material.set_color(queue, Color { color });
+            }
+            ui.label(format!("{} {}", i, material.name));
+        });
+    }
+}
+
+
+// local transforms of every node, rotation as euler angles in degrees
+pub fn graph_panel(ui: &mut egui::Ui, graph: &mut SceneGraph)
+{
+    egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui|
+    {
+        for i in 0..graph.len()
+        {
+            let id = NodeId(i);
+            let local = graph.node(id).local();
+            let mut position: [f32; 3] = local.position.into();
+            let euler = Euler::from(local.rotation);
+            let mut rotation = [Deg::from(euler.x).0, Deg::from(euler.y).0, Deg::from(euler.z).0];
+            let mut scale: [f32; 3] = local.scale.into();
+            let mut changed = [false; 3];
+            egui::CollapsingHeader::new(&graph.node(id).name).id_source(i).show(ui, |ui|
+            {
+                ui.label("position");
+                changed[0] = drag_vector(ui, &mut position, 0.05);
+                ui.label("rotation");
+                changed[1] = drag_vector(ui, &mut rotation, 1.0);
+                ui.label("scale");
+                changed[2] = drag_vector(ui, &mut scale, 0.01);
+            });
+
+            // only touched nodes are marked dirty
+            if changed.contains(&true)
+            {
+                let local = graph.local_mut(id);
+                if changed[0] { local.position = position.into(); }
+                if changed[1]
+                {
+                    let [x, y, z] = rotation.map(|a| Rad::from(Deg(a)));
+                    local.rotation = Quaternion::from(Euler::new(x, y, z));
+                }
+                if changed[2] { local.scale = scale.into(); }
+            }
+        }
+    });
+}
+// }}}
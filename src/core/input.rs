@@ -0,0 +1,321 @@
+use crate::core::assets;
+
+use std::collections::{HashMap, HashSet};
+use winit::event::*;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use serde::{Deserialize, Serialize};
+
+
+
+
+// BINDINGS {{{
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding
+{
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    // a wheel tick presses these for one frame, on an axis they add the distance scrolled
+    ScrollUp,
+    ScrollDown,
+}
+
+
+// an axis is the positive bindings minus the negative ones, held keys and buttons count 1 each
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Axis
+{
+    #[serde(default)]
+    pub positive: Vec<Binding>,
+    #[serde(default)]
+    pub negative: Vec<Binding>,
+}
+
+
+// what the config file holds, every name can have any number of bindings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputMap
+{
+    #[serde(default)]
+    pub actions: HashMap<String, Vec<Binding>>,
+    #[serde(default)]
+    pub axes: HashMap<String, Axis>,
+}
+
+
+impl InputMap
+{
+    pub fn bind(&mut self, action: &str, binding: Binding)
+    {
+        self.actions.entry(action.to_string()).or_default().push(binding);
+    }
+
+    // drops every binding of the action, for rebinding from scratch
+    pub fn unbind(&mut self, action: &str)
+    {
+        self.actions.remove(action);
+    }
+}
+
+
+pub async fn load_input_map(file_name: &str) -> anyhow::Result<InputMap>
+{
+    let text = assets::load_string(file_name).await?;
+    Ok(ron::from_str(&text)?)
+}
+// }}}
+
+
+
+
+// EVENTS {{{
+// the window and device events the engine reacts to, cut loose from winit so they can be saved
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent
+{
+    // keys and mouse buttons
+    Button { binding: Binding, pressed: bool },
+    // pixels, positive is up
+    Scroll(f32),
+    // cursor position normalized to 0..1 over the window, independent of the window size
+    Cursor(f64, f64),
+    MouseMotion(f64, f64),
+}
+
+
+impl InputEvent
+{
+    pub fn from_window(event: &WindowEvent, size: PhysicalSize<u32>) -> Option<Self>
+    {
+        match event
+        {
+            WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), state, .. }, .. } =>
+                Some(InputEvent::Button { binding: Binding::Key(*key), pressed: *state == ElementState::Pressed }),
+            WindowEvent::MouseInput { button, state, .. } =>
+                Some(InputEvent::Button { binding: Binding::Mouse(*button), pressed: *state == ElementState::Pressed }),
+            WindowEvent::MouseWheel { delta, .. } => Some(InputEvent::Scroll(match delta
+            {
+                // I'm assuming a line is about 100 pixels
+                MouseScrollDelta::LineDelta(_, lines) => lines * 100.0,
+                MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }) => *y as f32,
+            })),
+            WindowEvent::CursorMoved { position, .. } =>
+                Some(InputEvent::Cursor(position.x / size.width.max(1) as f64, position.y / size.height.max(1) as f64)),
+            _ => None,
+        }
+    }
+
+    pub fn from_device(event: &DeviceEvent) -> Option<Self>
+    {
+        match event
+        {
+            DeviceEvent::MouseMotion { delta } => Some(InputEvent::MouseMotion(delta.0, delta.1)),
+            _ => None,
+        }
+    }
+}
+// }}}
+
+
+
+
+// INPUT {{{
+// binding state gathered from window events, the per frame edges are cleared by end_frame
+#[derive(Debug)]
+pub struct Input
+{
+    pub map: InputMap,
+    held: HashSet<Binding>,
+    pressed: HashSet<Binding>,
+    released: HashSet<Binding>,
+    // pixels, positive is up
+    scroll: f32,
+}
+
+
+impl Input
+{
+    pub fn new(map: InputMap) -> Self
+    {
+        Self
+        {
+            map,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            scroll: 0.0,
+        }
+    }
+
+
+    // true for the events that can be bound, whether or not anything is bound to them
+    pub fn handle(&mut self, event: &InputEvent) -> bool
+    {
+        match *event
+        {
+            InputEvent::Button { binding, pressed } =>
+            {
+                self.set(binding, pressed);
+                true
+            }
+            InputEvent::Scroll(scroll) =>
+            {
+                self.scroll += scroll;
+                if scroll > 0.0 { self.pressed.insert(Binding::ScrollUp); }
+                if scroll < 0.0 { self.pressed.insert(Binding::ScrollDown); }
+                true
+            }
+            _ => false,
+        }
+    }
+
+
+    fn set(&mut self, binding: Binding, pressed: bool)
+    {
+        // key repeat sends more presses while held, only the first one is an edge
+        if pressed
+        {
+            if self.held.insert(binding) { self.pressed.insert(binding); }
+        }
+        else if self.held.remove(&binding)
+        {
+            self.released.insert(binding);
+        }
+    }
+
+
+    fn bindings(&self, action: &str) -> &[Binding]
+    {
+        self.map.actions.get(action).map_or(&[], |b| b.as_slice())
+    }
+
+    // went down since the last frame
+    pub fn pressed(&self, action: &str) -> bool
+    {
+        self.bindings(action).iter().any(|b| self.pressed.contains(b))
+    }
+
+    pub fn held(&self, action: &str) -> bool
+    {
+        self.bindings(action).iter().any(|b| self.held.contains(b))
+    }
+
+    pub fn released(&self, action: &str) -> bool
+    {
+        self.bindings(action).iter().any(|b| self.released.contains(b))
+    }
+
+
+    pub fn axis(&self, name: &str) -> f32
+    {
+        let Some(axis) = self.map.axes.get(name) else { return 0.0 };
+        let value = |b: &Binding| match b
+        {
+            Binding::ScrollUp => self.scroll.max(0.0),
+            Binding::ScrollDown => (-self.scroll).max(0.0),
+            b if self.held.contains(b) => 1.0,
+            _ => 0.0,
+        };
+        axis.positive.iter().map(value).sum::<f32>() - axis.negative.iter().map(value).sum::<f32>()
+    }
+
+
+    pub fn end_frame(&mut self)
+    {
+        self.pressed.clear();
+        self.released.clear();
+        self.scroll = 0.0;
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn key(key: VirtualKeyCode, pressed: bool) -> InputEvent
+    {
+        InputEvent::Button { binding: Binding::Key(key), pressed }
+    }
+
+    fn input() -> Input
+    {
+        let mut map = InputMap::default();
+        map.bind("jump", Binding::Key(VirtualKeyCode::Space));
+        map.bind("jump", Binding::Mouse(MouseButton::Left));
+        map.axes.insert("forward".to_string(), Axis
+        {
+            positive: vec![Binding::Key(VirtualKeyCode::W), Binding::Key(VirtualKeyCode::Up)],
+            negative: vec![Binding::Key(VirtualKeyCode::S)],
+        });
+        map.axes.insert("zoom".to_string(), Axis { positive: vec![Binding::ScrollUp], negative: vec![Binding::ScrollDown] });
+        Input::new(map)
+    }
+
+    #[test]
+    fn key_repeat_does_not_press_again()
+    {
+        let mut input = input();
+        assert!(input.handle(&key(VirtualKeyCode::Space, true)));
+        assert!(input.pressed("jump") && input.held("jump"));
+        input.end_frame();
+        // the os repeats the press while the key stays down
+        input.handle(&key(VirtualKeyCode::Space, true));
+        assert!(!input.pressed("jump") && input.held("jump"));
+        assert!(!input.pressed("unbound") && !input.held("unbound"));
+        assert!(!input.handle(&InputEvent::Cursor(0.5, 0.5)));
+    }
+
+    #[test]
+    fn release_clears_held()
+    {
+        let mut input = input();
+        input.handle(&key(VirtualKeyCode::Space, true));
+        input.handle(&InputEvent::Button { binding: Binding::Mouse(MouseButton::Left), pressed: true });
+        input.end_frame();
+        input.handle(&key(VirtualKeyCode::Space, false));
+        // the mouse button still holds the action
+        assert!(input.held("jump") && input.released("jump"));
+        input.handle(&InputEvent::Button { binding: Binding::Mouse(MouseButton::Left), pressed: false });
+        assert!(!input.held("jump"));
+        input.end_frame();
+        assert!(!input.released("jump"));
+        // a release without a press is not an edge
+        input.handle(&key(VirtualKeyCode::Space, false));
+        assert!(!input.released("jump"));
+    }
+
+    #[test]
+    fn opposing_keys_cancel()
+    {
+        let mut input = input();
+        input.handle(&key(VirtualKeyCode::W, true));
+        assert_eq!(input.axis("forward"), 1.0);
+        input.handle(&key(VirtualKeyCode::S, true));
+        assert_eq!(input.axis("forward"), 0.0);
+        input.handle(&key(VirtualKeyCode::Up, true));
+        assert_eq!(input.axis("forward"), 1.0);
+        input.handle(&key(VirtualKeyCode::W, false));
+        input.handle(&key(VirtualKeyCode::Up, false));
+        assert_eq!(input.axis("forward"), -1.0);
+        assert_eq!(input.axis("unknown"), 0.0);
+    }
+
+    #[test]
+    fn scroll_adds_to_axis_for_one_frame()
+    {
+        let mut input = input();
+        input.handle(&InputEvent::Scroll(120.0));
+        input.handle(&InputEvent::Scroll(-20.0));
+        assert_eq!(input.axis("zoom"), 100.0);
+        input.end_frame();
+        assert_eq!(input.axis("zoom"), 0.0);
+        input.handle(&InputEvent::Scroll(-50.0));
+        assert_eq!(input.axis("zoom"), -50.0);
+        input.end_frame();
+        assert_eq!(input.axis("zoom"), 0.0);
+    }
+}
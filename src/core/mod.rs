@@ -1,7 +1,23 @@
 pub mod camera;
+pub mod camera_path;
 pub mod model;
 pub mod texture;
 pub mod assets;
 pub mod renderer;
 pub mod engine;
 pub mod window;
+pub mod picking;
+pub mod controller;
+pub mod scene;
+pub mod graph;
+pub mod batch;
+pub mod bounds;
+pub mod culling;
+pub mod skeleton;
+pub mod animation;
+pub mod input;
+pub mod replay;
+pub mod stats;
+pub mod text;
+pub mod gui;
+pub mod debug;
@@ -1,6 +1,8 @@
 use crate::core::renderer::InstanceRaw;
 use crate::core::renderer::VertexBuffer;
+use crate::core::renderer::ModelVertex;
 use crate::core::texture::Texture;
+use crate::core::bounds::{Aabb, BoundingSphere};
 
 use wgpu::util::DeviceExt;
 
@@ -14,6 +16,16 @@ pub struct Mesh
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
+    // cpu side copy of the geometry, used for picking and debug drawing
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+    // object space bounds, used for culling
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    // coarser index buffers over the same vertices, finest first
+    pub lods: Vec<Lod>,
+    pub morph_targets: Option<MorphTargets>,
 } 
 
 
@@ -21,6 +33,7 @@ impl Mesh {
     
     pub fn new(
         device: &wgpu::Device,
+        name: &str,
         vertices: Vec<impl VertexBuffer + bytemuck::Pod + bytemuck::Zeroable>,
         indices: Vec<u32>,
     ) -> Self {
@@ -36,18 +49,390 @@ impl Mesh {
                 usage: wgpu::BufferUsages::INDEX,
             });
 
+            let positions : Vec<[f32; 3]> = vertices.iter().map(|v| v.position()).collect();
+            let normals : Vec<[f32; 3]> = vertices.iter().map(|v| v.normal()).collect();
+            let aabb = Aabb::from_points(&positions);
+            let bounding_sphere = BoundingSphere::from_points(&positions, &aabb);
+
             Self {
-                name: "some name".to_string(),
+                name: name.to_string(),
                 vertex_buffer,
                 index_buffer,
                 num_elements: indices.len() as u32,
+                positions,
+                normals,
+                indices,
+                aabb,
+                bounding_sphere,
+                lods: Vec::new(),
+                morph_targets: None,
+            }
+    }
+
+
+    // one level per ratio of the full triangle count, each simplified from the one before,
+    // levels that could not be reduced any further are dropped
+    pub fn generate_lods(&mut self, device: &wgpu::Device, ratios: &[f32]) {
+        let full = self.indices.len() / 3;
+        let mut indices = self.indices.clone();
+        let mut error = 0.0f32;
+        self.lods.clear();
+        for &ratio in ratios {
+            let target = (full as f32 * ratio) as usize;
+            let (simplified, e) = simplify(&self.positions, &indices, target);
+            if simplified.is_empty() || simplified.len() >= indices.len() {
+                break;
+            }
+            indices = simplified;
+            // each pass measures against its input, the distances to the full mesh add up
+            error += e;
+            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("{} lod {}", self.name, self.lods.len() + 1)),
+                contents: bytemuck::cast_slice(&indices),
+                usage: wgpu::BufferUsages::INDEX,
+            });
+            self.lods.push(Lod { index_buffer, num_elements: indices.len() as u32, error });
+        }
+    }
+
+
+    // level 0 is the full mesh, the others index into lods
+    pub fn lod(&self, level: usize) -> (&wgpu::Buffer, u32) {
+        match level.checked_sub(1).and_then(|i| self.lods.get(i)) {
+            Some(lod) => (&lod.index_buffer, lod.num_elements),
+            None => (&self.index_buffer, self.num_elements),
+        }
+    }
+
+
+    // coarsest level whose error stays under LOD_PIXEL_ERROR on screen,
+    // pixels_per_unit is the size of one object space unit at the mesh's distance
+    pub fn select_lod(&self, pixels_per_unit: f32) -> usize {
+        self.lods.iter()
+            .take_while(|lod| lod.error * pixels_per_unit <= LOD_PIXEL_ERROR)
+            .count()
+    }
+}
+
+
+
+
+// }}}
+
+
+// LOD {{{
+// how far in pixels a simplified surface may be off before a finer level is picked
+pub const LOD_PIXEL_ERROR: f32 = 1.0;
+
+
+pub struct Lod
+{
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+    // largest distance from the full mesh, in object space
+    pub error: f32,
+}
+
+
+// symmetric 4x4 matrix, upper triangle row by row
+type Quadric = [f64; 10];
+
+
+fn plane_quadric(n: [f64; 3], d: f64, weight: f64) -> Quadric {
+    let [a, b, c] = n;
+    [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|q| q * weight)
+}
+
+
+fn quadric_add(a: &Quadric, b: &Quadric) -> Quadric {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+
+
+// sum of squared distances from p to the planes in q
+fn quadric_error(q: &Quadric, p: [f64; 3]) -> f64 {
+    let [x, y, z] = p;
+    q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+        + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+        + q[7] * z * z + 2.0 * q[8] * z
+        + q[9]
+}
+
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] { [a[0] - b[0], a[1] - b[1], a[2] - b[2]] }
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 { a[0] * b[0] + a[1] * b[1] + a[2] * b[2] }
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+
+fn face_normal(points: &[[f64; 3]], t: [u32; 3]) -> [f64; 3] {
+    let [a, b, c] = t.map(|v| points[v as usize]);
+    cross(sub(b, a), sub(c, a))
+}
+
+
+// moving from onto to, cheapest direction first
+struct Collapse
+{
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool { self.cost == other.cost }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(other)) }
+}
+// reversed, the heap pops the cheapest collapse
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering { other.cost.total_cmp(&self.cost) }
+}
+
+
+// Quadric error metric simplification by half edge collapses. Every vertex of the result is one
+// of the input vertices, so a level only needs its own index buffer. Vertices split along uv or
+// normal seams are welded by position first so collapses never tear the surface open.
+// Returns the new indices and the largest error introduced, in the units of positions.
+pub fn simplify(positions: &[[f32; 3]], indices: &[u32], target_triangles: usize) -> (Vec<u32>, f32) {
+    use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut points: Vec<[f64; 3]> = Vec::new();
+    let mut representative: Vec<u32> = Vec::new();
+    let weld: Vec<u32> = positions.iter().enumerate().map(|(i, p)| {
+        *welded.entry(p.map(f32::to_bits)).or_insert_with(|| {
+            points.push(p.map(f64::from));
+            representative.push(i as u32);
+            points.len() as u32 - 1
+        })
+    }).collect();
+    let n = points.len();
+
+    let original: Vec<[u32; 3]> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut triangles: Vec<[u32; 3]> = original.iter().map(|t| t.map(|v| weld[v as usize])).collect();
+    let degenerate = |t: &[u32; 3]| t[0] == t[1] || t[1] == t[2] || t[0] == t[2];
+    let mut alive: Vec<bool> = triangles.iter().map(|t| !degenerate(t)).collect();
+    let mut live = alive.iter().filter(|&&a| a).count();
+
+    // every face adds its plane to its corners, open edges add a plane standing on them
+    // so borders keep their shape
+    let mut quadrics = vec![[0.0; 10]; n];
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); n];
+    // ordered, so the same mesh always sums and collapses the same way
+    let mut edges: BTreeMap<(u32, u32), (u32, usize)> = BTreeMap::new();
+    for (i, &t) in triangles.iter().enumerate() {
+        if !alive[i] { continue; }
+        let normal = face_normal(&points, t);
+        let length = dot(normal, normal).sqrt();
+        for k in 0..3 {
+            let v = t[k] as usize;
+            adjacency[v].push(i);
+            if length > 0.0 {
+                let normal = normal.map(|x| x / length);
+                quadrics[v] = quadric_add(&quadrics[v], &plane_quadric(normal, -dot(normal, points[v]), 1.0));
+            }
+            let (a, b) = (t[k], t[(k + 1) % 3]);
+            edges.entry((a.min(b), a.max(b))).or_insert((0, i)).0 += 1;
+        }
+    }
+    const BORDER_WEIGHT: f64 = 10.0;
+    for (&(a, b), &(count, i)) in &edges {
+        if count != 1 { continue; }
+        let edge = sub(points[b as usize], points[a as usize]);
+        let side = cross(edge, face_normal(&points, triangles[i]));
+        let length = dot(side, side).sqrt();
+        if length == 0.0 { continue; }
+        let side = side.map(|x| x / length);
+        let q = plane_quadric(side, -dot(side, points[a as usize]), BORDER_WEIGHT);
+        for v in [a, b] {
+            quadrics[v as usize] = quadric_add(&quadrics[v as usize], &q);
+        }
+    }
+
+    let mut versions = vec![0u32; n];
+    let mut heap = BinaryHeap::new();
+    let candidate = |quadrics: &[Quadric], versions: &[u32], a: u32, b: u32| {
+        let q = quadric_add(&quadrics[a as usize], &quadrics[b as usize]);
+        let onto_b = quadric_error(&q, points[b as usize]);
+        let onto_a = quadric_error(&q, points[a as usize]);
+        let (from, to, cost) = if onto_b <= onto_a { (a, b, onto_b) } else { (b, a, onto_a) };
+        Collapse { cost: cost.max(0.0), from, to, versions: (versions[from as usize], versions[to as usize]) }
+    };
+    for &(a, b) in edges.keys() {
+        heap.push(candidate(&quadrics, &versions, a, b));
+    }
+
+    let mut collapsed = vec![false; n];
+    let mut max_cost = 0.0f64;
+    while live > target_triangles {
+        let Some(c) = heap.pop() else { break };
+        let (from, to) = (c.from as usize, c.to as usize);
+        if collapsed[from] || collapsed[to] || versions[from] != c.versions.0 || versions[to] != c.versions.1 {
+            continue;
+        }
+
+        // faces that only move must not turn over
+        let flips = adjacency[from].iter().any(|&i| {
+            let t = triangles[i];
+            if !alive[i] || t.contains(&c.to) { return false; }
+            let moved = t.map(|v| if v == c.from { c.to } else { v });
+            dot(face_normal(&points, t), face_normal(&points, moved)) <= 0.0
+        });
+        if flips { continue; }
+
+        collapsed[from] = true;
+        for i in std::mem::take(&mut adjacency[from]) {
+            if !alive[i] { continue; }
+            for v in triangles[i].iter_mut() {
+                if *v == c.from { *v = c.to; }
+            }
+            if degenerate(&triangles[i]) {
+                alive[i] = false;
+                live -= 1;
+            } else {
+                adjacency[to].push(i);
             }
+        }
+        adjacency[to].retain(|&i| alive[i]);
+        quadrics[to] = quadric_add(&quadrics[to], &quadrics[from]);
+        versions[to] += 1;
+        max_cost = max_cost.max(c.cost);
+
+        let mut neighbours: Vec<u32> = adjacency[to].iter()
+            .flat_map(|&i| triangles[i])
+            .filter(|&v| v != c.to)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for v in neighbours {
+            heap.push(candidate(&quadrics, &versions, c.to, v));
+        }
     }
+
+    // untouched corners keep their own vertex and with it their uv and normal
+    let simplified = triangles.iter().zip(&original).zip(&alive)
+        .filter(|(_, &alive)| alive)
+        .flat_map(|((t, o), _)| {
+            std::array::from_fn::<u32, 3, _>(|k| {
+                if t[k] == weld[o[k] as usize] { o[k] } else { representative[t[k] as usize] }
+            })
+        })
+        .collect();
+    (simplified, max_cost.sqrt() as f32)
+}
+// }}}
+
+
+// MORPH TARGETS {{{
+// offsets from the base mesh, normals may be left empty
+pub struct MorphTarget
+{
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+}
+
+
+pub struct MorphTargets
+{
+    // for every target and vertex a position delta followed by a normal delta, both padded to vec4
+    pub buffer: wgpu::Buffer,
+    pub count: u32,
+    pub vertices: u32,
+    pub default_weights: Vec<f32>,
+}
+
+
+impl MorphTargets {
+    pub fn new(device: &wgpu::Device, targets: &[MorphTarget], vertices: usize, default_weights: &[f32]) -> Self {
+        let mut deltas: Vec<[f32; 4]> = Vec::with_capacity(targets.len() * vertices * 2);
+        for target in targets {
+            for v in 0..vertices {
+                let [x, y, z] = target.positions.get(v).copied().unwrap_or_default();
+                let [nx, ny, nz] = target.normals.get(v).copied().unwrap_or_default();
+                deltas.extend([[x, y, z, 0.0], [nx, ny, nz, 0.0]]);
+            }
+        }
+        // empty storage bindings are not allowed
+        if deltas.is_empty() { deltas.push([0.0; 4]); }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Target Buffer"),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let mut default_weights = default_weights.to_vec();
+        default_weights.resize(targets.len(), 0.0);
+        Self { buffer, count: targets.len() as u32, vertices: vertices as u32, default_weights }
+    }
+}
+
+
+// one set of target weights per instance of a morphed mesh, the instance count is fixed
+pub struct MorphWeights
+{
+    weights: Vec<f32>,
+    targets: usize,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    dirty: bool,
 }
 
 
+impl MorphWeights {
+    // every instance starts with the targets' default weights
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, targets: &MorphTargets, instances: usize) -> Self {
+        let count = targets.count as usize;
+        let mut weights: Vec<f32> = targets.default_weights.iter().copied().cycle().take(count * instances).collect();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Morph Weights Buffer"),
+            size: (weights.len().max(1) * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Params Buffer"),
+            contents: bytemuck::cast_slice(&[targets.count, targets.vertices, 0, 0]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: targets.buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: params.as_entire_binding() },
+            ],
+            label: Some("morph_bind_group"),
+        });
+        if weights.is_empty() { weights.push(0.0); }
+        Self { weights, targets: count, buffer, bind_group, dirty: true }
+    }
 
+    pub fn get(&self, instance: usize) -> &[f32] {
+        let start = (instance * self.targets).min(self.weights.len());
+        &self.weights[start..(start + self.targets).min(self.weights.len())]
+    }
 
+    // extra weights are ignored, missing ones keep their value
+    pub fn set(&mut self, instance: usize, weights: &[f32]) {
+        let start = instance * self.targets;
+        let Some(slot) = self.weights.get_mut(start..start + self.targets) else { return };
+        for (w, &v) in slot.iter_mut().zip(weights) {
+            *w = v;
+        }
+        self.dirty = true;
+    }
+
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if self.dirty {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.weights));
+            self.dirty = false;
+        }
+    }
+}
 // }}}
 
 
@@ -57,13 +442,68 @@ pub struct Material
     pub name: String,
     pub diffuse_color: Color,
     pub diffuse_texture: Texture,
+    pub color_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
 
+impl Material {
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        name: &str,
+        diffuse_texture: Texture,
+        diffuse_color: Color,
+    ) -> Self {
+        let color_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Material Color Buffer"),
+                contents: bytemuck::cast_slice(&[diffuse_color]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: color_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some(name),
+        });
+
+        Self {
+            name: name.to_string(),
+            diffuse_color,
+            diffuse_texture,
+            color_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn set_color(&mut self, queue: &wgpu::Queue, color: Color) {
+        self.diffuse_color = color;
+        queue.write_buffer(&self.color_buffer, 0, bytemuck::cast_slice(&[color]));
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.diffuse_color.color[3] < 1.0
+    }
+}
+
+
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Color
 {
     pub color: [f32; 4],
@@ -74,12 +514,14 @@ pub struct Color
 // MODEL {{{
 pub struct Model 
 {
+    pub name: String,
     pub meshes: Vec<Mesh>,
     pub materials: Vec<u32>,
 } // }}}
 
 
 // INSTANCING {{{
+#[derive(Clone, Copy, Debug)]
 pub struct Instance 
 {
     pub position: cgmath::Vector3<f32>,
@@ -89,21 +531,601 @@ pub struct Instance
 
 
 impl Instance {
+    // drops shear, which only shows up under non-uniformly scaled parents
+    // the axes are made orthonormal so a zero scale still leaves a rotation, a mirror ends up as a negative z scale
+    pub fn from_matrix(m: cgmath::Matrix4<f32>) -> Self {
+        use cgmath::InnerSpace;
+        let (x, y, z) = (m.x.truncate(), m.y.truncate(), m.z.truncate());
+        let unit = |v: cgmath::Vector3<f32>| if v.magnitude2() > 1e-12 { Some(v.normalize()) } else { None };
+        // a flattened axis takes its direction from the other two
+        let right = unit(x).or(unit(y.cross(z))).unwrap_or(cgmath::Vector3::unit_x());
+        let any = if right.z.abs() < 0.9 { cgmath::Vector3::unit_z().cross(right) } else { cgmath::Vector3::unit_x().cross(right) };
+        let up = unit(y - right * right.dot(y)).or(unit(z.cross(right))).unwrap_or(any.normalize());
+        let forward = right.cross(up);
+        let scale = cgmath::Vector3::new(x.magnitude(), y.magnitude(), z.dot(forward));
+        let rotation = cgmath::Matrix3::from_cols(right, up, forward);
+        Self {
+            position: m.w.truncate(),
+            rotation: rotation.into(),
+            scale,
+        }
+    }
+
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.position) * 
+        cgmath::Matrix4::from(self.rotation) * 
+        cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             // model: (cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation)).into(),
-            model: (
-                cgmath::Matrix4::from_translation(self.position) * 
-                cgmath::Matrix4::from(self.rotation) * 
-                cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)).into(),
+            model: self.to_matrix().into(),
         }
     }
 }
 
 
+// instances live on the cpu and are mirrored into a vertex buffer,
+// edits go through the methods below so upload only writes what changed
 pub struct Instances
 {
-    pub instances: Vec<Instance>,
+    instances: Vec<Instance>,
     pub buffer: wgpu::Buffer,
+    capacity: usize,
+    dirty: Vec<std::ops::Range<usize>>,
+    // changes whenever buffer is reallocated, unique across every Instances
+    buffer_id: u64,
 } 
+
+
+fn next_buffer_id() -> u64 {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+
+impl Instances {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let capacity = instances.len();
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(&instance_data),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
+            }
+        );
+        Self { instances, buffer, capacity, dirty: Vec::new(), buffer_id: next_buffer_id() }
+    }
+
+    // identifies the current buffer, for caches of bind groups that point at it
+    pub fn buffer_id(&self) -> u64 { self.buffer_id }
+
+    pub fn len(&self) -> usize { self.instances.len() }
+    pub fn is_empty(&self) -> bool { self.instances.is_empty() }
+    pub fn get(&self, i: usize) -> Option<&Instance> { self.instances.get(i) }
+    pub fn iter(&self) -> std::slice::Iter<'_, Instance> { self.instances.iter() }
+
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut Instance> {
+        if i < self.instances.len() { self.dirty.push(i..i + 1); }
+        self.instances.get_mut(i)
+    }
+
+    pub fn set(&mut self, i: usize, instance: Instance) {
+        self.instances[i] = instance;
+        self.dirty.push(i..i + 1);
+    }
+
+    pub fn push(&mut self, instance: Instance) -> usize {
+        let i = self.instances.len();
+        self.instances.push(instance);
+        self.dirty.push(i..i + 1);
+        i
+    }
+
+    // moves the last instance into the freed slot, so indices past i are not stable
+    pub fn remove(&mut self, i: usize) -> Instance {
+        let instance = self.instances.swap_remove(i);
+        if i < self.instances.len() { self.dirty.push(i..i + 1); }
+        instance
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.instances.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty.clear();
+    }
+
+    // writes the dirty ranges, or everything when the buffer had to grow
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let stride = std::mem::size_of::<InstanceRaw>();
+        if let Some(capacity) = grown_capacity(self.capacity, self.instances.len()) {
+            self.capacity = capacity;
+            self.buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Instance Buffer"),
+                size: (self.capacity * stride) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.buffer_id = next_buffer_id();
+            self.dirty.clear();
+            self.dirty.push(0..self.instances.len());
+        }
+
+        for r in coalesce(std::mem::take(&mut self.dirty), self.instances.len()) {
+            let data = self.instances[r.clone()].iter().map(Instance::to_raw).collect::<Vec<_>>();
+            queue.write_buffer(&self.buffer, (r.start * stride) as wgpu::BufferAddress, bytemuck::cast_slice(&data));
+        }
+    }
+}
+
+
+// the new capacity when len no longer fits, doubling keeps pushes amortized
+fn grown_capacity(capacity: usize, len: usize) -> Option<usize> {
+    if len > capacity { Some(len.max(capacity * 2)) } else { None }
+}
+
+
+// sorts and merges overlapping or touching ranges, clipped to len
+fn coalesce(mut dirty: Vec<std::ops::Range<usize>>, len: usize) -> Vec<std::ops::Range<usize>> {
+    dirty.sort_by_key(|r| r.start);
+    let mut merged : Vec<std::ops::Range<usize>> = Vec::new();
+    for r in dirty {
+        let r = r.start.min(len)..r.end.min(len);
+        if r.is_empty() { continue; }
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+// }}}
+
+
+
+
+// PRIMITIVES {{{
+// Generated shapes are centered on the origin with z up, faces wind counter clockwise seen from
+// outside and v runs from the top down. Pass the result straight to Mesh::new.
+use std::f32::consts::{PI, TAU};
+
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 { v } else { v.map(|x| x / length) }
+}
+
+
+// grid of (cols + 1) x (rows + 1) vertices, rows from the top, (u, v) are both in 0..=1
+fn grid(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    cols: u32,
+    rows: u32,
+    vertex: impl Fn(f32, f32) -> ModelVertex,
+) {
+    let base = vertices.len() as u32;
+    for i in 0..=rows {
+        for j in 0..=cols {
+            vertices.push(vertex(j as f32 / cols as f32, i as f32 / rows as f32));
+        }
+    }
+    for i in 0..rows {
+        for j in 0..cols {
+            let a = base + i * (cols + 1) + j;
+            let (b, c, d) = (a + 1, a + cols + 1, a + cols + 2);
+            indices.extend([a, c, d, a, d, b]);
+        }
+    }
+}
+
+
+// surface of revolution around z, profile points are (radius, z, normal radius, normal z)
+// from the top down. Triangles that collapse onto the axis are skipped.
+fn lathe(vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>, sectors: u32, profile: &[(f32, f32, f32, f32)]) {
+    let base = vertices.len() as u32;
+    let rows = profile.len() as u32 - 1;
+    let mut grid_indices = Vec::new();
+    grid(vertices, &mut grid_indices, sectors, rows, |u, v| {
+        let (r, z, nr, nz) = profile[(v * rows as f32).round() as usize];
+        let (sin, cos) = (u * TAU).sin_cos();
+        ModelVertex {
+            position: [r * cos, r * sin, z],
+            uv: [u, v],
+            normal: normalize([nr * cos, nr * sin, nz]),
+        }
+    });
+    let row = |i: u32| ((i - base) / (sectors + 1)) as usize;
+    for t in grid_indices.chunks_exact(3) {
+        // two corners in the same row on the axis are the same point
+        let collapsed = (0..3).any(|k| {
+            let (p, q) = (t[k], t[(k + 1) % 3]);
+            row(p) == row(q) && profile[row(p)].0 == 0.0
+        });
+        if !collapsed {
+            indices.extend_from_slice(t);
+        }
+    }
+}
+
+
+// flat disc facing up or down, uvs map the disc into the unit square
+fn disc(vertices: &mut Vec<ModelVertex>, indices: &mut Vec<u32>, sectors: u32, radius: f32, z: f32, up: bool) {
+    let normal = [0.0, 0.0, if up { 1.0 } else { -1.0 }];
+    let center = vertices.len() as u32;
+    vertices.push(ModelVertex { position: [0.0, 0.0, z], uv: [0.5, 0.5], normal });
+    for j in 0..=sectors {
+        let (sin, cos) = (j as f32 / sectors as f32 * TAU).sin_cos();
+        let sin = if up { sin } else { -sin };
+        vertices.push(ModelVertex {
+            position: [radius * cos, radius * sin, z],
+            uv: [0.5 + 0.5 * cos, 0.5 - 0.5 * sin],
+            normal,
+        });
+    }
+    for j in 0..sectors {
+        indices.extend([center, center + 1 + j, center + 2 + j]);
+    }
+}
+
+
+// square facing normal, right x up has to point along normal
+fn face(
+    vertices: &mut Vec<ModelVertex>,
+    indices: &mut Vec<u32>,
+    subdivisions: u32,
+    center: [f32; 3],
+    right: [f32; 3],
+    up: [f32; 3],
+    normal: [f32; 3],
+) {
+    grid(vertices, indices, subdivisions, subdivisions, |u, v| {
+        let (x, y) = (u * 2.0 - 1.0, 1.0 - v * 2.0);
+        ModelVertex {
+            position: std::array::from_fn(|k| center[k] + right[k] * x + up[k] * y),
+            uv: [u, v],
+            normal,
+        }
+    });
+}
+
+
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let profile: Vec<_> = (0..=stacks)
+        .map(|i| {
+            let (sin, cos) = (i as f32 / stacks as f32 * PI).sin_cos();
+            // exact zero on the poles so the fan triangles are dropped
+            let sin = if i == 0 || i == stacks { 0.0 } else { sin };
+            (radius * sin, radius * cos, sin, cos)
+        })
+        .collect();
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    lathe(&mut vertices, &mut indices, sectors.max(3), &profile);
+    (vertices, indices)
+}
+
+
+// subdivided icosahedron, triangles evenly sized unlike the uv sphere. Triangles crossing the
+// u seam are cut along it so u stays in 0..=1.
+pub fn icosphere(radius: f32, subdivisions: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    use std::collections::HashMap;
+
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points: Vec<[f32; 3]> = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ].map(normalize).to_vec();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let (p, q) = (points[a as usize], points[b as usize]);
+                points.push(normalize([p[0] + q[0], p[1] + q[1], p[2] + q[2]]));
+                points.len() as u32 - 1
+            })
+        };
+        triangles = triangles.iter().flat_map(|&[a, b, c]| {
+            let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+            [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+        }).collect();
+    }
+
+    let u_of = |p: [f32; 3]| p[1].atan2(p[0]).rem_euclid(TAU) / TAU;
+    let mut vertices = Vec::new();
+    // (point, u32::MAX, u) for points of the sphere, (edge start, edge end, u) where an edge crosses the seam
+    let mut shared: HashMap<(u32, u32, u32), u32> = HashMap::new();
+    let mut vertex = |key: (u32, u32), p: [f32; 3], u: f32| {
+        *shared.entry((key.0, key.1, u.to_bits())).or_insert_with(|| {
+            let normal = normalize(p);
+            vertices.push(ModelVertex {
+                position: p.map(|x| x * radius),
+                uv: [u, normal[2].clamp(-1.0, 1.0).acos() / PI],
+                normal,
+            });
+            vertices.len() as u32 - 1
+        })
+    };
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+    for tri in triangles {
+        let corners = tri.map(|i| points[i as usize]);
+        let us = corners.map(u_of);
+        let max = us.iter().copied().fold(0.0, f32::max);
+        let min = us.iter().copied().fold(1.0, f32::min);
+        if max - min <= 0.5 {
+            for k in 0..3 {
+                indices.push(vertex((tri[k], u32::MAX), corners[k], us[k]));
+            }
+            continue;
+        }
+        // the seam is the half plane y = 0, x > 0. Cutting the triangle along it leaves a piece
+        // above with u near 0 and one below with u near 1, both in winding order
+        let (mut above, mut below) = (Vec::new(), Vec::new());
+        for k in 0..3 {
+            let (p, q) = (corners[k], corners[(k + 1) % 3]);
+            let key = (tri[k], u32::MAX);
+            if p[1] >= 0.0 { above.push(vertex(key, p, if p[1] == 0.0 { 0.0 } else { us[k] })); }
+            if p[1] <= 0.0 { below.push(vertex(key, p, if p[1] == 0.0 { 1.0 } else { us[k] })); }
+            if p[1] * q[1] < 0.0 {
+                // from the lower index so both triangles on the edge compute the same point
+                let (a, b) = (tri[k].min(tri[(k + 1) % 3]), tri[k].max(tri[(k + 1) % 3]));
+                let (pa, pb) = (points[a as usize], points[b as usize]);
+                let t = pa[1] / (pa[1] - pb[1]);
+                let mut x: [f32; 3] = std::array::from_fn(|i| pa[i] + (pb[i] - pa[i]) * t);
+                x[1] = 0.0;
+                above.push(vertex((a, b), x, 0.0));
+                below.push(vertex((a, b), x, 1.0));
+            }
+        }
+        for piece in [above, below] {
+            for k in 1..piece.len().saturating_sub(1) {
+                indices.extend([piece[0], piece[k], piece[k + 1]]);
+            }
+        }
+    }
+    (vertices, indices)
+}
+
+
+pub fn cube(size: f32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let h = size / 2.0;
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    // normal, right, up
+    for (n, r, u) in [
+        ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ] {
+        let scale = |v: [f32; 3]| v.map(|x: f32| x * h);
+        face(&mut vertices, &mut indices, 1, scale(n), scale(r), scale(u), n);
+    }
+    (vertices, indices)
+}
+
+
+// square in the xy plane facing up, split into subdivisions x subdivisions quads
+pub fn plane(size: f32, subdivisions: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let h = size / 2.0;
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    face(&mut vertices, &mut indices, subdivisions.max(1), [0.0; 3], [h, 0.0, 0.0], [0.0, h, 0.0], [0.0, 0.0, 1.0]);
+    (vertices, indices)
+}
+
+
+pub fn cylinder(radius: f32, height: f32, sectors: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let (sectors, h) = (sectors.max(3), height / 2.0);
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    lathe(&mut vertices, &mut indices, sectors, &[(radius, h, 1.0, 0.0), (radius, -h, 1.0, 0.0)]);
+    disc(&mut vertices, &mut indices, sectors, radius, h, true);
+    disc(&mut vertices, &mut indices, sectors, radius, -h, false);
+    (vertices, indices)
+}
+
+
+// apex up, the side normals lean towards it
+pub fn cone(radius: f32, height: f32, sectors: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let (sectors, h) = (sectors.max(3), height / 2.0);
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    lathe(&mut vertices, &mut indices, sectors, &[(0.0, h, height, radius), (radius, -h, height, radius)]);
+    disc(&mut vertices, &mut indices, sectors, radius, -h, false);
+    (vertices, indices)
+}
+
+
+// ring around z, major is the distance from the center to the middle of the tube
+pub fn torus(major: f32, minor: f32, sectors: u32, sides: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let sides = sides.max(3);
+    // the tube starts on top and turns outwards first
+    let profile: Vec<_> = (0..=sides)
+        .map(|i| {
+            let (sin, cos) = (PI / 2.0 - i as f32 / sides as f32 * TAU).sin_cos();
+            (major + minor * cos, minor * sin, cos, sin)
+        })
+        .collect();
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    lathe(&mut vertices, &mut indices, sectors.max(3), &profile);
+    (vertices, indices)
+}
+
+
+// cylinder of the given height with a hemisphere of stacks rows on each end
+pub fn capsule(radius: f32, height: f32, sectors: u32, stacks: u32) -> (Vec<ModelVertex>, Vec<u32>) {
+    let (stacks, h) = (stacks.max(1), height / 2.0);
+    let hemisphere = |i: u32| {
+        let (sin, cos) = (i as f32 / stacks as f32 * PI / 2.0).sin_cos();
+        (if i == 0 { 0.0 } else { sin }, cos)
+    };
+    let mut profile = Vec::new();
+    for i in 0..=stacks {
+        let (sin, cos) = hemisphere(i);
+        profile.push((radius * sin, h + radius * cos, sin, cos));
+    }
+    for i in (0..=stacks).rev() {
+        let (sin, cos) = hemisphere(i);
+        profile.push((radius * sin, -h - radius * cos, sin, -cos));
+    }
+    let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+    lathe(&mut vertices, &mut indices, sectors.max(3), &profile);
+    (vertices, indices)
+}
 // }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use cgmath::{InnerSpace, Matrix4, Quaternion, Rad, Rotation3, SquareMatrix, Vector3};
+
+    fn assert_matches(m: Matrix4<f32>)
+    {
+        let instance = Instance::from_matrix(m);
+        let q: Quaternion<f32> = instance.rotation;
+        assert!((q.magnitude() - 1.0).abs() < 1e-4, "{:?} is not a rotation", q);
+        let back = instance.to_matrix();
+        for (a, b) in [(back.x, m.x), (back.y, m.y), (back.z, m.z), (back.w, m.w)]
+        {
+            assert!((a - b).magnitude() < 1e-4, "{:?} != {:?}", back, m);
+        }
+    }
+
+    #[test]
+    fn from_matrix_survives_zero_scale_and_mirrors()
+    {
+        let turn = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from(Quaternion::from_axis_angle(Vector3::unit_z(), Rad(0.7)));
+        assert_matches(turn * Matrix4::from_nonuniform_scale(2.0, 3.0, 4.0));
+        assert_matches(turn * Matrix4::from_nonuniform_scale(2.0, 3.0, -4.0));
+        assert_matches(turn * Matrix4::from_nonuniform_scale(-1.0, 1.0, 1.0));
+        assert_matches(turn * Matrix4::from_nonuniform_scale(0.0, 1.0, 1.0));
+        assert_matches(turn * Matrix4::from_nonuniform_scale(1.0, 0.0, 1.0));
+        assert_matches(turn * Matrix4::from_scale(0.0));
+        assert!(Instance::from_matrix(Matrix4::from_scale(0.0)).to_matrix().determinant() == 0.0);
+    }
+
+    fn normal(positions: &[[f32; 3]], t: &[u32]) -> Vector3<f32>
+    {
+        let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vector3::from(positions[i as usize]));
+        (b - a).cross(c - a)
+    }
+
+    #[test]
+    fn simplify_reaches_the_target_without_flipping()
+    {
+        let (vertices, indices) = icosphere(1.0, 3);
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        let (simplified, error) = simplify(&positions, &indices, 100);
+        assert_eq!(simplified.len() / 3, 100);
+        assert!(error > 0.0 && error.is_finite(), "error {}", error);
+        // every face of a sphere faces away from its center
+        for t in simplified.chunks_exact(3)
+        {
+            let center = t.iter().map(|&i| Vector3::from(positions[i as usize])).sum::<Vector3<f32>>();
+            assert!(normal(&positions, t).dot(center) > 0.0, "{:?} turned over", t);
+        }
+        assert_eq!(simplify(&positions, &indices, 100).0, simplified);
+    }
+
+    #[test]
+    fn simplify_keeps_the_border()
+    {
+        let (vertices, indices) = plane(2.0, 8);
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        let (simplified, _) = simplify(&positions, &indices, 8);
+        assert_eq!(simplified.len() / 3, 8);
+        // a flat square stays a square, the area and the corners survive
+        let area: f32 = simplified.chunks_exact(3).map(|t| normal(&positions, t).magnitude() / 2.0).sum();
+        assert!((area - 4.0).abs() < 1e-3, "area {}", area);
+        for corner in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]]
+        {
+            assert!(simplified.iter().any(|&i| positions[i as usize][..2] == corner), "lost {:?}", corner);
+        }
+        for t in simplified.chunks_exact(3)
+        {
+            assert!(normal(&positions, t).z > 0.0);
+        }
+    }
+
+    #[test]
+    fn coalesce_merges_overlapping_and_adjacent_ranges()
+    {
+        // overlapping and out of order
+        assert_eq!(coalesce(vec![4..8, 2..5, 6..7], 10), vec![2..8]);
+        // touching ranges become one write
+        assert_eq!(coalesce(vec![3..4, 0..3, 4..5], 10), vec![0..5]);
+        // disjoint ranges stay apart
+        assert_eq!(coalesce(vec![7..9, 0..2], 10), vec![0..2, 7..9]);
+        // clipped to len, ranges past a truncate are dropped
+        assert_eq!(coalesce(vec![1..2, 3..6, 8..9, 5..5], 4), vec![1..2, 3..4]);
+        assert!(coalesce(Vec::new(), 4).is_empty());
+    }
+
+    #[test]
+    fn capacity_grows_geometrically()
+    {
+        assert_eq!(grown_capacity(8, 8), None);
+        assert_eq!(grown_capacity(8, 3), None);
+        assert_eq!(grown_capacity(8, 9), Some(16));
+        // one large push skips straight to what is needed
+        assert_eq!(grown_capacity(8, 40), Some(40));
+        assert_eq!(grown_capacity(0, 1), Some(1));
+        // pushing one at a time reallocates only a logarithmic number of times
+        let (mut capacity, mut reallocations) = (1, 0);
+        for len in 1..=1000
+        {
+            if let Some(c) = grown_capacity(capacity, len) { capacity = c; reallocations += 1; }
+        }
+        assert_eq!((capacity, reallocations), (1024, 10));
+    }
+
+    #[test]
+    fn primitives_face_their_normals()
+    {
+        let shapes = [
+            ("uv_sphere", uv_sphere(1.5, 16, 8)),
+            ("icosphere", icosphere(1.5, 0)),
+            ("icosphere", icosphere(1.5, 3)),
+            ("cube", cube(2.0)),
+            ("plane", plane(4.0, 3)),
+            ("cylinder", cylinder(1.0, 2.0, 12)),
+            ("cone", cone(1.0, 2.0, 12)),
+            ("torus", torus(2.0, 0.5, 16, 8)),
+            ("capsule", capsule(0.5, 1.0, 12, 4)),
+        ];
+        for (name, (vertices, indices)) in shapes
+        {
+            assert!(!indices.is_empty() && indices.len() % 3 == 0, "{}", name);
+            for v in &vertices
+            {
+                let n = Vector3::from(v.normal);
+                assert!((n.magnitude() - 1.0).abs() < 1e-4, "{}: normal {:?}", name, v.normal);
+                assert!(v.uv.iter().all(|x| (0.0..=1.0).contains(x)), "{}: uv {:?}", name, v.uv);
+            }
+            for tri in indices.chunks_exact(3)
+            {
+                let [a, b, c] = [0, 1, 2].map(|k| &vertices[tri[k] as usize]);
+                let (pa, pb, pc) = (Vector3::from(a.position), Vector3::from(b.position), Vector3::from(c.position));
+                let face = (pb - pa).cross(pc - pa);
+                let normal = Vector3::from(a.normal) + Vector3::from(b.normal) + Vector3::from(c.normal);
+                assert!(face.magnitude() > 0.0, "{}: degenerate triangle {:?}", name, tri);
+                assert!(face.dot(normal) > 0.0, "{}: triangle {:?} winds away from its normals", name, tri);
+            }
+        }
+    }
+}
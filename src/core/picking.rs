@@ -0,0 +1,500 @@
+use crate::core::model::{ Model, Instances, Mesh };
+use crate::core::renderer::{ BindGroupLayouts, ModelVertex, InstanceRaw, VertexBuffer, DepthMode };
+use crate::core::texture::Texture;
+
+use cgmath::*;
+use std::sync::mpsc;
+
+
+
+
+// RAY {{{
+#[derive(Debug, Copy, Clone)]
+pub struct Ray
+{
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+
+impl Ray
+{
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self
+    {
+        Self { origin, direction: direction.normalize() }
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32>
+    {
+        self.origin + self.direction * t
+    }
+
+    // the direction is deliberately left unnormalized so that t stays the same along the world ray
+    pub fn transform(&self, matrix: Matrix4<f32>) -> Self
+    {
+        Self
+        {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+
+    // möller-trumbore, both faces count as a hit
+    pub fn intersect_triangle(&self, a: Point3<f32>, b: Point3<f32>, c: Point3<f32>) -> Option<f32>
+    {
+        const EPSILON: f32 = 1e-7;
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < EPSILON { return None; }
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) { return None; }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 { return None; }
+        let t = edge2.dot(q) * inv_det;
+        if t > EPSILON { Some(t) } else { None }
+    }
+
+    pub fn intersect_mesh(&self, mesh: &Mesh) -> Option<f32>
+    {
+        self.intersect_triangles(&mesh.positions, &mesh.indices)
+    }
+
+    // nearest hit over an indexed triangle list
+    pub fn intersect_triangles(&self, positions: &[[f32; 3]], indices: &[u32]) -> Option<f32>
+    {
+        indices
+            .chunks_exact(3)
+            .filter_map(|tri|
+            {
+                let a = Point3::from(positions[tri[0] as usize]);
+                let b = Point3::from(positions[tri[1] as usize]);
+                let c = Point3::from(positions[tri[2] as usize]);
+                self.intersect_triangle(a, b, c)
+            })
+            .fold(None, |closest: Option<f32>, t| Some(closest.map_or(t, |c| c.min(t))))
+    }
+}
+// }}}
+
+
+// CPU PICKING {{{
+#[derive(Debug, Copy, Clone)]
+pub struct PickHit
+{
+    pub model: usize,
+    pub instance: usize,
+    pub distance: f32,
+    pub point: Point3<f32>,
+}
+
+
+// model in the hit is the index into targets
+pub fn pick(ray: &Ray, targets: &[(&Model, &Instances)]) -> Option<PickHit>
+{
+    let geometry: Vec<PickGeometry> = targets
+        .iter()
+        .map(|(model, instances)|
+        (
+            model.meshes.iter().map(|mesh| (mesh.positions.as_slice(), mesh.indices.as_slice())).collect(),
+            instances.iter().map(|instance| instance.to_matrix()).collect(),
+        ))
+        .collect();
+    pick_geometry(ray, &geometry)
+}
+
+
+// the triangle lists of one model and the transforms of its instances, picking needs nothing from the gpu side
+pub type PickGeometry<'a> = (Vec<(&'a [[f32; 3]], &'a [u32])>, Vec<Matrix4<f32>>);
+
+pub fn pick_geometry(ray: &Ray, targets: &[PickGeometry]) -> Option<PickHit>
+{
+    let mut closest: Option<PickHit> = None;
+    for (model_index, (meshes, transforms)) in targets.iter().enumerate()
+    {
+        for (instance_index, transform) in transforms.iter().enumerate()
+        {
+            let inverse = match transform.invert()
+            {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+            let local = ray.transform(inverse);
+            for (positions, indices) in meshes
+            {
+                if let Some(t) = local.intersect_triangles(positions, indices)
+                {
+                    if closest.is_none_or(|hit| t < hit.distance)
+                    {
+                        closest = Some(PickHit
+                        {
+                            model: model_index,
+                            instance: instance_index,
+                            distance: t,
+                            point: ray.at(t),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    closest
+}
+// }}}
+
+
+// GPU PICKING {{{
+// Every instance is drawn into an R32Uint target as (model << 16 | instance) + 1, zero means nothing was hit.
+// The texel under the cursor is copied into a staging buffer and read back without blocking the frame.
+const ID_UNIFORM_STRIDE: wgpu::BufferAddress = 256;
+const ID_MAX_MODELS: u64 = 64;
+const ID_MAX_INSTANCES: usize = 1 << 16;
+
+
+pub struct IdBuffer
+{
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub depth_texture: Texture,
+    pipeline: wgpu::RenderPipeline,
+    model_buffer: wgpu::Buffer,
+    model_bind_group: wgpu::BindGroup,
+    staging: wgpu::Buffer,
+    pending: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    copied: bool,
+    size: wgpu::Extent3d,
+    depth: DepthMode,
+}
+
+
+impl IdBuffer
+{
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, layouts: &BindGroupLayouts, depth: DepthMode) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/id.wgsl"));
+        let model_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry
+                {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer
+                    {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(4),
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("id_model_bind_group_layout"),
+        });
+
+        let model_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Id Model Buffer"),
+            size: ID_UNIFORM_STRIDE * ID_MAX_MODELS,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let model_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &model_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &model_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(4),
+                    }),
+                },
+            ],
+            label: Some("id_model_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("id_pipeline_layout"),
+            bind_group_layouts: &[&layouts.camera, &model_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("id_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: wgpu::TextureFormat::R32Uint,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                // both faces, like the cpu pick
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Id Staging Buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        let (texture, view) = Self::create_target(device, size);
+        let depth_texture = Texture::create_depth_texture(device, size, "id_depth_texture", wgpu::FilterMode::Nearest, depth);
+
+        Self
+        {
+            texture,
+            view,
+            depth_texture,
+            pipeline,
+            model_buffer,
+            model_bind_group,
+            staging,
+            pending: None,
+            copied: false,
+            size,
+            depth,
+        }
+    }
+
+
+    fn create_target(device: &wgpu::Device, size: wgpu::Extent3d) -> (wgpu::Texture, wgpu::TextureView)
+    {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("id_texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Uint,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32)
+    {
+        self.size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        (self.texture, self.view) = Self::create_target(device, self.size);
+        self.depth_texture = Texture::create_depth_texture(device, self.size, "id_depth_texture", wgpu::FilterMode::Nearest, self.depth);
+    }
+
+
+    // draws the id pass and copies the texel under (x, y) into the staging buffer,
+    // returns false while an earlier readback is still pending so the caller can keep the click queued
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        camera: &wgpu::BindGroup,
+        targets: &[(&Model, &Instances)],
+        x: u32,
+        y: u32) -> bool
+    {
+        if self.pending.is_some() || self.copied { return false; }
+        // a click outside the target has nothing to pick
+        if x >= self.size.width || y >= self.size.height { return true; }
+
+        for i in 0..targets.len().min(ID_MAX_MODELS as usize)
+        {
+            queue.write_buffer(&self.model_buffer, i as u64 * ID_UNIFORM_STRIDE, bytemuck::cast_slice(&[i as u32]));
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Id Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth.clear_value()),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(0, camera, &[]);
+            for (i, (model, instances)) in targets.iter().enumerate().take(ID_MAX_MODELS as usize)
+            {
+                let offset = i as wgpu::DynamicOffset * ID_UNIFORM_STRIDE as wgpu::DynamicOffset;
+                render_pass.set_bind_group(1, &self.model_bind_group, &[offset]);
+                // the instance index only has 16 bits in the id, anything past that is left out
+                let count = instances.len().min(ID_MAX_INSTANCES);
+                if count < instances.len()
+                {
+                    log::warn!("picking: model {} has {} instances, only the first {} can be picked", i, instances.len(), ID_MAX_INSTANCES);
+                }
+                for mesh in &model.meshes
+                {
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass.set_vertex_buffer(1, instances.buffer.slice(..));
+                    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..mesh.num_elements, 0, 0..count as u32);
+                }
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.staging,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        );
+        self.copied = true;
+        true
+    }
+
+
+    // call after the encoder holding the copy has been submitted
+    pub fn map(&mut self)
+    {
+        if !self.copied || self.pending.is_some() { return; }
+        let (sender, receiver) = mpsc::channel();
+        self.staging.slice(..).map_async(wgpu::MapMode::Read, move |result| { sender.send(result).ok(); });
+        self.pending = Some(receiver);
+        self.copied = false;
+    }
+
+
+    // None while the readback is still in flight, Some(None) when the click hit nothing
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Option<(usize, usize)>>
+    {
+        let receiver = self.pending.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let result = receiver.try_recv().ok()?;
+        self.pending = None;
+        if let Err(error) = result
+        {
+            log::warn!("id buffer readback failed: {}", error);
+            return Some(None);
+        }
+        let id = {
+            let data = self.staging.slice(..).get_mapped_range();
+            bytemuck::cast_slice::<u8, u32>(&data[..4])[0]
+        };
+        self.staging.unmap();
+        if id == 0 { return Some(None); }
+        let id = id - 1;
+        Some(Some(((id >> 16) as usize, (id & 0xffff) as usize)))
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a unit quad in the xy plane facing +z
+    const QUAD_POSITIONS: [[f32; 3]; 4] = [[-0.5, -0.5, 0.0], [0.5, -0.5, 0.0], [0.5, 0.5, 0.0], [-0.5, 0.5, 0.0]];
+    const QUAD_INDICES: [u32; 6] = [0, 1, 2, 0, 2, 3];
+
+    fn down(x: f32, y: f32) -> Ray
+    {
+        Ray::new(Point3::new(x, y, 5.0), -Vector3::unit_z())
+    }
+
+    #[test]
+    fn triangle_hit_miss_and_back_face()
+    {
+        let (a, b, c) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(down(0.25, 0.25).intersect_triangle(a, b, c), Some(5.0));
+        assert_eq!(down(0.75, 0.75).intersect_triangle(a, b, c), None);
+        // from below the triangle faces away, picking still counts it
+        let up = Ray::new(Point3::new(0.25, 0.25, -2.0), Vector3::unit_z());
+        assert_eq!(up.intersect_triangle(a, b, c), Some(2.0));
+        // behind the origin
+        let away = Ray::new(Point3::new(0.25, 0.25, 1.0), Vector3::unit_z());
+        assert_eq!(away.intersect_triangle(a, b, c), None);
+        // parallel to the triangle
+        let along = Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::unit_x());
+        assert_eq!(along.intersect_triangle(a, b, c), None);
+    }
+
+    #[test]
+    fn triangle_list_returns_nearest()
+    {
+        // the same quad twice, the second copy one unit higher
+        let positions: Vec<[f32; 3]> = QUAD_POSITIONS.iter()
+            .chain(QUAD_POSITIONS.iter().map(|p| [p[0], p[1], 1.0]).collect::<Vec<_>>().iter())
+            .copied()
+            .collect();
+        let indices: Vec<u32> = QUAD_INDICES.iter().chain(QUAD_INDICES.map(|i| i + 4).iter()).copied().collect();
+        assert_eq!(down(0.1, -0.2).intersect_triangles(&positions, &indices), Some(4.0));
+        assert_eq!(down(2.0, 0.0).intersect_triangles(&positions, &indices), None);
+    }
+
+    #[test]
+    fn pick_finds_nearest_instance()
+    {
+        let quad = vec![(&QUAD_POSITIONS[..], &QUAD_INDICES[..])];
+        let targets: Vec<PickGeometry> = vec![
+            (quad.clone(), vec![Matrix4::from_translation(Vector3::new(3.0, 0.0, 2.0)), Matrix4::from_translation(Vector3::new(0.0, 0.0, 1.0))]),
+            // scaled up so the ray still lands on it, and a degenerate instance that can not be inverted
+            (quad.clone(), vec![Matrix4::from_translation(Vector3::new(0.0, 0.0, -1.0)) * Matrix4::from_scale(4.0), Matrix4::from_scale(0.0)]),
+            (quad, vec![Matrix4::from_translation(Vector3::new(0.0, 0.0, 2.0)) * Matrix4::from_angle_x(Deg(180.0))]),
+        ];
+        let hit = pick_geometry(&down(0.2, 0.2), &targets).unwrap();
+        assert_eq!((hit.model, hit.instance), (2, 0));
+        assert!((hit.distance - 3.0).abs() < 1e-5);
+        assert!((hit.point - Point3::new(0.2, 0.2, 2.0)).magnitude() < 1e-5);
+
+        let hit = pick_geometry(&down(1.5, 1.5), &targets).unwrap();
+        assert_eq!((hit.model, hit.instance), (1, 0));
+        assert!((hit.distance - 6.0).abs() < 1e-5);
+
+        assert!(pick_geometry(&down(10.0, 0.0), &targets).is_none());
+    }
+}
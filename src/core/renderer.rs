@@ -1,9 +1,9 @@
 // This file contains layouts for gpu input
 use crate::core::camera::Camera;
 use crate::core::texture::Texture;
-use crate::core::model::{ Material, Model, Instances, Mesh };
+use crate::core::model::{ Material, Model, Instances, Mesh, MorphWeights };
+use crate::core::skeleton::Skin;
 
-use std::ops::Range;
 use wgpu::util::DeviceExt;
 
 
@@ -47,6 +47,11 @@ pub trait VertexBuffer
         }
     }
 
+    fn position(&self) -> [f32; 3];
+
+    // zero for layouts without one
+    fn normal(&self) -> [f32; 3] { [0.0; 3] }
+
     fn new_vertex_buffer (device : &wgpu::Device, vertices : &[Self]) -> wgpu::Buffer
     where Self : Sized + bytemuck::Pod
     {
@@ -89,6 +94,19 @@ pub struct ModelVertex
 }
 
 
+// ModelVertex plus the four joints moving it and how much each one does
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex
+{
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub normal: [f32; 3],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct VertexOnly
@@ -105,10 +123,15 @@ pub struct VertexUV
 }
 
 
-impl VertexBuffer for VertexOnly {}
+impl VertexBuffer for VertexOnly
+{
+    fn position(&self) -> [f32; 3] { self.position }
+}
 
 impl VertexBuffer for VertexUV
 {
+    fn position(&self) -> [f32; 3] { self.position }
+
     fn desc() -> wgpu::VertexBufferLayout<'static>
     {
         use std::mem;
@@ -133,6 +156,9 @@ impl VertexBuffer for VertexUV
 
 
 impl VertexBuffer for InstanceRaw {
+    // translation column of the model matrix
+    fn position(&self) -> [f32; 3] { [self.model[3][0], self.model[3][1], self.model[3][2]] }
+
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -174,6 +200,9 @@ impl VertexBuffer for InstanceRaw {
 
 
 impl VertexBuffer for ModelVertex {
+    fn position(&self) -> [f32; 3] { self.position }
+    fn normal(&self) -> [f32; 3] { self.normal }
+
     fn desc() -> wgpu::VertexBufferLayout<'static> {
         use std::mem;
         wgpu::VertexBufferLayout {
@@ -198,6 +227,48 @@ impl VertexBuffer for ModelVertex {
             ],
         }
     }
+}
+
+
+
+impl VertexBuffer for SkinnedVertex {
+    fn position(&self) -> [f32; 3] { self.position }
+    fn normal(&self) -> [f32; 3] { self.normal }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
 } // end VERTEX BUFFER LAYOUTS  }}}
 
 
@@ -321,6 +392,74 @@ impl Resource for Framebuffer
         })
     }
 }
+
+
+
+impl Resource for Skin
+{
+    fn desc( device : &wgpu::Device ) -> wgpu::BindGroupLayout
+    {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry 
+                {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer 
+                    {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
+            label: Some("skin_bind_group_layout"),
+        })
+    }
+}
+
+
+
+impl Resource for MorphWeights
+{
+    fn desc( device : &wgpu::Device ) -> wgpu::BindGroupLayout
+    {
+        let storage = |binding| wgpu::BindGroupLayoutEntry 
+        {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer 
+            {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // target deltas
+                storage(0),
+                // weights
+                storage(1),
+                wgpu::BindGroupLayoutEntry 
+                {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer 
+                    {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("morph_bind_group_layout"),
+        })
+    }
+}
 // end BIND GROUP LAYOUTS }}}
 
 
@@ -334,11 +473,57 @@ impl Resource for Framebuffer
 //     PostProcess,
 // }
 //
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepthMode
+{
+    Standard,
+    // near plane at 1.0, far plane (or infinity) at 0.0, keeps float precision where it is needed
+    ReverseZ,
+}
+
+impl DepthMode
+{
+    pub fn clear_value(&self) -> f32
+    {
+        match self
+        {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    pub fn near_value(&self) -> f32
+    {
+        1.0 - self.clear_value()
+    }
+
+    pub fn compare(&self) -> wgpu::CompareFunction
+    {
+        match self
+        {
+            DepthMode::Standard => wgpu::CompareFunction::Less,
+            DepthMode::ReverseZ => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    pub fn sampler_compare(&self) -> wgpu::CompareFunction
+    {
+        match self
+        {
+            DepthMode::Standard => wgpu::CompareFunction::LessEqual,
+            DepthMode::ReverseZ => wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+}
+
+
 pub struct BindGroupLayouts
 {
     pub camera: wgpu::BindGroupLayout,
     pub material: wgpu::BindGroupLayout,
     pub framebuffer: wgpu::BindGroupLayout,
+    pub skin: wgpu::BindGroupLayout,
+    pub morph: wgpu::BindGroupLayout,
 }
 
 pub enum PipelineResources
@@ -346,6 +531,10 @@ pub enum PipelineResources
     Camera,
     Material,
     Framebuffer,
+    // joint matrices, bound per mesh with Draw::set_skin
+    Skin,
+    // target deltas and instance weights, bound per mesh with Draw::set_morph
+    Morph,
 }
 
 pub enum PipelineBuffers
@@ -353,13 +542,16 @@ pub enum PipelineBuffers
     Model,
     Instance,
     VertexOnly,
-    VertexUV
+    VertexUV,
+    Skinned,
 }
 
 
 pub struct RenderPipelineWrapper
 {
     pub pipeline: wgpu::RenderPipeline,
+    // same pipeline with depth writes off for transparent materials, only when depth is tested
+    pub transparent: Option<wgpu::RenderPipeline>,
     pub resources: Vec<PipelineResources>,
     pub vertex_buffers: Vec<PipelineBuffers>,
 }
@@ -373,7 +565,7 @@ impl RenderPipelineWrapper
         device : &wgpu::Device, 
         config: &wgpu::SurfaceConfiguration,
         shader: &wgpu::ShaderModule,
-        depth_stencil: bool,
+        depth: Option<DepthMode>,
         resources : Vec<PipelineResources>,
         vertex_buffers : Vec<PipelineBuffers>,
         layouts : &BindGroupLayouts,
@@ -387,6 +579,8 @@ impl RenderPipelineWrapper
                 PipelineResources::Camera =>        { &layouts.camera },
                 PipelineResources::Material =>      { &layouts.material },
                 PipelineResources::Framebuffer =>   { &layouts.framebuffer },
+                PipelineResources::Skin =>          { &layouts.skin },
+                PipelineResources::Morph =>         { &layouts.morph },
             }
         }).collect();
         let buffers : Vec<wgpu::VertexBufferLayout<'static>> = vertex_buffers.iter().map(|x| 
@@ -397,6 +591,7 @@ impl RenderPipelineWrapper
                 PipelineBuffers::Instance =>    { InstanceRaw::desc() },
                 PipelineBuffers::VertexOnly =>  { VertexOnly::desc() },
                 PipelineBuffers::VertexUV =>    { VertexUV::desc() },
+                PipelineBuffers::Skinned =>     { SkinnedVertex::desc() },
             }
         }).collect();
 
@@ -408,23 +603,7 @@ impl RenderPipelineWrapper
                 push_constant_ranges: &[],
             }
         );
-        let ds : Option<wgpu::DepthStencilState>;
-        if depth_stencil == true
-        { 
-            ds =  Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            })
-        }
-        else
-        {
-            ds = None;
-        }
-
-        let pipeline = device.create_render_pipeline(
+        let create = |depth_write_enabled: bool| device.create_render_pipeline(
             &wgpu::RenderPipelineDescriptor 
             {
                 label,
@@ -460,7 +639,13 @@ impl RenderPipelineWrapper
                     unclipped_depth: false,
                     conservative: false,
                 },
-                depth_stencil: ds,
+                depth_stencil: depth.map(|mode| wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled,
+                    depth_compare: mode.compare(),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
                 multisample: wgpu::MultisampleState 
                 {
                     count: 1,
@@ -470,7 +655,9 @@ impl RenderPipelineWrapper
                 multiview: None,
             }
         );
-        Self { pipeline, resources, vertex_buffers }
+        let pipeline = create(true);
+        let transparent = depth.map(|_| create(false));
+        Self { pipeline, transparent, resources, vertex_buffers }
     }
 
 }
@@ -501,6 +688,27 @@ macro_rules! create_render_pass {
                     occlusion_query_set: None,
         })
     };
+    // Plain render pass timed by GpuTimer::pass
+    ($encoder:expr, $view:expr, $timestamps:expr ) => {
+        $encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Simple Pass"),
+                color_attachments: &[Some(
+                    wgpu::RenderPassColorAttachment 
+                    {
+                        view: &$view,
+                        resolve_target: None,
+                        ops: wgpu::Operations 
+                        {
+                            load: wgpu::LoadOp::Clear( wgpu::Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0, }   ),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }
+                )],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: $timestamps,
+                    occlusion_query_set: None,
+        })
+    };
     // Render pass with framebuffer parameter
     // ($encoder:expr, $framebuffer:expr) => {
     //     let depth_stencil_attachment = match &$framebuffer.depth_texture {
@@ -531,6 +739,11 @@ macro_rules! create_render_pass {
     // };
 
     ($encoder:expr, $view:expr, $z_buffer:expr) => {
+        $crate::create_render_pass!($encoder, $view, $z_buffer, $crate::core::renderer::DepthMode::Standard)
+    };
+
+    // Render pass with depth buffer cleared for the given DepthMode
+    ($encoder:expr, $view:expr, $z_buffer:expr, $depth_mode:expr) => {
     $encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Simple Pass"),
             color_attachments: &[Some(
@@ -548,7 +761,7 @@ macro_rules! create_render_pass {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &$z_buffer.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear($depth_mode.clear_value()),
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -605,6 +818,16 @@ pub trait Draw<'a>
         materials : &'a Material,
         camera: &'a wgpu::BindGroup) -> ();
 
+    fn set_skin(
+        &mut self,
+        pipeline: &'a RenderPipelineWrapper,
+        skin: &'a Skin);
+
+    fn set_morph(
+        &mut self,
+        pipeline: &'a RenderPipelineWrapper,
+        weights: &'a MorphWeights);
+
     fn draw_mesh(
 
         &mut self,
@@ -615,7 +838,14 @@ pub trait Draw<'a>
         &mut self,
         mesh: &'a Mesh,
         instances: &'a Instances,
-        num_instances: Range<u32>,
+    );
+
+    // instance_buffer and indirect_buffer usually come from a culling::CullTarget
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        instance_buffer: &'a wgpu::Buffer,
+        indirect_buffer: &'a wgpu::Buffer,
     );
 }
 
@@ -638,11 +868,37 @@ where
                 PipelineResources::Camera => { self.set_bind_group(i as u32, camera, &[]); },
                 PipelineResources::Material => { self.set_bind_group(i as u32, &material.bind_group, &[]); },
                 PipelineResources::Framebuffer => { self.set_bind_group(i as u32, &material.bind_group, &[]); },
+                PipelineResources::Skin => {},
+                PipelineResources::Morph => {},
             }
         }
     }
 
 
+    fn set_skin(
+            &mut self,
+            pipeline: &'b RenderPipelineWrapper,
+            skin: &'b Skin)
+    {
+        if let Some(i) = pipeline.resources.iter().position(|r| matches!(r, PipelineResources::Skin))
+        {
+            self.set_bind_group(i as u32, &skin.bind_group, &[]);
+        }
+    }
+
+
+    fn set_morph(
+            &mut self,
+            pipeline: &'b RenderPipelineWrapper,
+            weights: &'b MorphWeights)
+    {
+        if let Some(i) = pipeline.resources.iter().position(|r| matches!(r, PipelineResources::Morph))
+        {
+            self.set_bind_group(i as u32, &weights.bind_group, &[]);
+        }
+    }
+
+
     fn draw_mesh(
         &mut self,
         mesh: &'b Mesh,) 
@@ -655,13 +911,24 @@ where
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
-        instances: &'b Instances,
-        num_instances: Range<u32>,) 
+        instances: &'b Instances,) 
     {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_vertex_buffer(1, instances.buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        self.draw_indexed(0..mesh.num_elements, 0, num_instances);
+        self.draw_indexed(0..mesh.num_elements, 0, 0..instances.len() as u32);
+    }
+
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        instance_buffer: &'b wgpu::Buffer,
+        indirect_buffer: &'b wgpu::Buffer,) 
+    {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, instance_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed_indirect(indirect_buffer, 0);
     }
 }
 
@@ -0,0 +1,195 @@
+use crate::core::camera::{CameraState, Projection};
+use crate::core::controller::{CameraController, CameraInput};
+use crate::core::input::{Input, InputEvent, InputMap};
+
+use serde::{Deserialize, Serialize};
+use instant::Duration;
+
+
+
+
+// RECORDING {{{
+// the events one update consumed and the dt it ran with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputFrame
+{
+    pub dt: Duration,
+    pub events: Vec<InputEvent>,
+}
+
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputRecording
+{
+    pub frames: Vec<InputFrame>,
+    // events since the last end_frame
+    #[serde(skip)]
+    pending: Vec<InputEvent>,
+}
+
+
+impl InputRecording
+{
+    pub fn new() -> Self
+    {
+        Self { frames: Vec::new(), pending: Vec::new() }
+    }
+
+    pub fn push_event(&mut self, event: InputEvent)
+    {
+        self.pending.push(event);
+    }
+
+    // closes the frame, call it with the dt update was given
+    pub fn end_frame(&mut self, dt: Duration)
+    {
+        let events = std::mem::take(&mut self.pending);
+        self.frames.push(InputFrame { dt, events });
+    }
+
+    pub fn duration(&self) -> Duration
+    {
+        self.frames.iter().map(|f| f.dt).sum()
+    }
+
+
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()>
+    {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self>
+    {
+        let text = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+}
+// }}}
+
+
+
+
+// REPLAY {{{
+// hands out the recorded frames in order, one per update
+#[derive(Debug)]
+pub struct InputReplay
+{
+    pub recording: InputRecording,
+    frame: usize,
+}
+
+
+impl InputReplay
+{
+    pub fn new(recording: InputRecording) -> Self
+    {
+        Self { recording, frame: 0 }
+    }
+
+    pub fn next_frame(&mut self) -> Option<&InputFrame>
+    {
+        let frame = self.recording.frames.get(self.frame)?;
+        self.frame += 1;
+        Some(frame)
+    }
+
+    pub fn is_finished(&self) -> bool
+    {
+        self.frame >= self.recording.frames.len()
+    }
+}
+
+
+// runs the camera side of a recording without a window or gpu and returns the state after every
+// frame, the same steps Camera::update takes. mouse motion is only recorded while the app applied
+// it, so all of it is applied here
+pub fn replay_camera(
+    recording: &InputRecording,
+    map: InputMap,
+    controller: &mut dyn CameraController,
+    state: &mut CameraState,
+    projection: &mut Projection) -> Vec<CameraState>
+{
+    let mut input = Input::new(map);
+    let mut camera_input = CameraInput::new();
+    let mut states = Vec::with_capacity(recording.frames.len());
+    for frame in &recording.frames
+    {
+        for event in &frame.events
+        {
+            match *event
+            {
+                InputEvent::Cursor(x, y) => camera_input.process_mouse_pos(x, y),
+                InputEvent::MouseMotion(dx, dy) => camera_input.process_mouse(dx, dy),
+                _ => { input.handle(event); }
+            }
+        }
+        camera_input.read_actions(&input);
+        input.end_frame();
+        controller.update(state, projection, &camera_input, frame.dt);
+        camera_input.reset();
+        states.push(state.clone());
+    }
+    states
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::core::controller::FpsController;
+    use crate::core::input::{Axis, Binding};
+    use cgmath::prelude::*;
+    use cgmath::{Deg, Euler, Point3, Quaternion, Rad, Vector3};
+    use winit::event::VirtualKeyCode;
+
+    fn replay(recording: &InputRecording) -> Vec<CameraState>
+    {
+        let mut map = InputMap::default();
+        map.axes.insert("move_forward".into(), Axis { positive: vec![Binding::Key(VirtualKeyCode::W)], negative: Vec::new() });
+        let mut state = CameraState
+        {
+            position: Point3::new(0.0, -10.0, 1.0),
+            rotation: Quaternion::from(Euler::new(Rad(-std::f32::consts::FRAC_PI_2), Rad(0.0), Rad(0.0))),
+            velocity: Vector3::zero(),
+        };
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        replay_camera(recording, map, &mut FpsController::new(10.0, 0.2), &mut state, &mut projection)
+    }
+
+    #[test]
+    fn saved_recording_replays_the_same_camera()
+    {
+        let w = |pressed| InputEvent::Button { binding: Binding::Key(VirtualKeyCode::W), pressed };
+        let mut recording = InputRecording::new();
+        for i in 0..120
+        {
+            if i == 10 { recording.push_event(w(true)); }
+            if i % 7 == 0 { recording.push_event(InputEvent::MouseMotion(3.0, -1.5)); }
+            if i == 80 { recording.push_event(w(false)); }
+            recording.end_frame(Duration::from_micros(16_000 + (i * 37 % 900) as u64));
+        }
+
+        let path = std::env::temp_dir().join(format!("fstop_replay_{}.ron", std::process::id()));
+        recording.save(&path).unwrap();
+        let loaded = InputRecording::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.frames, recording.frames);
+
+        let first = replay(&recording);
+        let second = replay(&loaded);
+        assert!((first[79].position - first[10].position).magnitude() > 1.0, "the camera never moved");
+        for (a, b) in first.iter().zip(&second)
+        {
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.rotation, b.rotation);
+        }
+    }
+}
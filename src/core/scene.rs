@@ -0,0 +1,502 @@
+use crate::core::assets;
+use crate::core::camera::Projection;
+use crate::core::model::{Model, Material, Color, Instance, Instances};
+use crate::core::graph::{SceneGraph, NodeId};
+use crate::core::skeleton::{Skeleton, Clip};
+use crate::core::animation::{
+    Animation, Animatable, Target, Track, Easing, Playback, Clock, MaterialFloat,
+    TransformAnimation, CameraAnimation, MaterialAnimation,
+};
+use crate::core::texture::Texture;
+
+use cgmath::prelude::*;
+use cgmath::{Deg, Point3, Quaternion, Rad, Vector3};
+use serde::Deserialize;
+
+
+
+
+// FORMAT {{{
+#[derive(Debug, Deserialize)]
+pub struct SceneDesc
+{
+    pub materials: Vec<MaterialDesc>,
+    pub models: Vec<ModelDesc>,
+    #[serde(default)]
+    pub lights: Vec<Light>,
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub animations: Vec<AnimationDesc>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub enum MaterialDesc
+{
+    // every material of a .mtl file, in file order
+    Mtl(String),
+    Color
+    {
+        name: String,
+        color: [f32; 4],
+        // a plain white texel when left out, so the color shows as it is
+        #[serde(default)]
+        texture: Option<String>,
+    },
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct ModelDesc
+{
+    pub name: String,
+    // .obj, or .gltf for skinned and morphing meshes
+    pub file: String,
+    // indices into the flattened material list
+    #[serde(default)]
+    pub materials: Vec<u32>,
+    #[serde(default)]
+    pub instances: Vec<InstanceDesc>,
+    // fractions of the full triangle count, one simplified level each
+    #[serde(default)]
+    pub lods: Vec<f32>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct InstanceDesc
+{
+    // defaults to the model name
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    // axis and angle in degrees, a zero axis means no rotation
+    #[serde(default)]
+    pub rotation: ([f32; 3], f32),
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    // (model name, transform relative to this instance)
+    #[serde(default)]
+    pub children: Vec<(String, InstanceDesc)>,
+}
+
+
+fn unit_scale() -> [f32; 3] { [1.0; 3] }
+
+
+// axis and angle in degrees, a zero axis means no rotation
+fn axis_angle((axis, degrees): ([f32; 3], f32)) -> Quaternion<f32>
+{
+    let axis = Vector3::from(axis);
+    if axis.is_zero() {
+        Quaternion::one()
+    } else {
+        Quaternion::from_axis_angle(axis.normalize(), Deg(degrees))
+    }
+}
+
+
+impl InstanceDesc
+{
+    pub fn to_instance(&self) -> Instance
+    {
+        Instance
+        {
+            position: self.position.into(),
+            rotation: axis_angle(self.rotation),
+            scale: self.scale.into(),
+        }
+    }
+
+
+    fn add_to(&self, graph: &mut SceneGraph, model: &str, parent: Option<NodeId>)
+    {
+        let name = if self.name.is_empty() { model } else { &self.name };
+        let id = graph.add(name, Some(model), self.to_instance(), parent);
+        for (child_model, child) in &self.children
+        {
+            child.add_to(graph, child_model, Some(id));
+        }
+    }
+
+
+    fn models<'a>(&'a self, names: &mut Vec<&'a str>)
+    {
+        for (model, child) in &self.children
+        {
+            names.push(model);
+            child.models(names);
+        }
+    }
+
+
+    // node names of this instance and its children, as add_to names them
+    fn names<'a>(&'a self, model: &'a str, names: &mut Vec<&'a str>)
+    {
+        names.push(if self.name.is_empty() { model } else { &self.name });
+        for (child_model, child) in &self.children
+        {
+            child.names(child_model, names);
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Light
+{
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct CameraDesc
+{
+    pub position: [f32; 3],
+    // degrees
+    pub yaw: f32,
+    pub pitch: f32,
+    pub projection: ProjectionDesc,
+}
+
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ProjectionDesc
+{
+    // fovy in degrees
+    Perspective { fovy: f32, znear: f32, zfar: f32 },
+    Orthographic { zoom: f32, znear: f32, zfar: f32 },
+}
+
+
+impl SceneDesc
+{
+    // checks what serde can not, material_count is the length of the flattened material list
+    pub fn validate(&self, material_count: usize) -> anyhow::Result<()>
+    {
+        let mut children = Vec::new();
+        self.models.iter().flat_map(|m| &m.instances).for_each(|i| i.models(&mut children));
+        if let Some(child) = children.iter().find(|&&c| !self.models.iter().any(|m| m.name == c))
+        {
+            anyhow::bail!("child instance uses unknown model {:?}", child);
+        }
+        for m in &self.models
+        {
+            if let Some(i) = m.materials.iter().find(|&&i| i as usize >= material_count)
+            {
+                anyhow::bail!("model {:?} uses material {} but only {} are defined", m.name, i, material_count);
+            }
+        }
+        let mut nodes = Vec::new();
+        self.models.iter().for_each(|m| m.instances.iter().for_each(|i| i.names(&m.name, &mut nodes)));
+        for a in &self.animations
+        {
+            match &a.target
+            {
+                TargetDesc::Node { node, .. } if !nodes.contains(&node.as_str()) =>
+                    anyhow::bail!("animation {:?} moves unknown node {:?}", a.name, node),
+                TargetDesc::Material { material, .. } if *material as usize >= material_count =>
+                    anyhow::bail!("animation {:?} uses material {} but only {} are defined", a.name, material, material_count),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl CameraDesc
+{
+    pub fn position(&self) -> Point3<f32> { self.position.into() }
+    pub fn yaw(&self) -> Deg<f32> { Deg(self.yaw) }
+    pub fn pitch(&self) -> Deg<f32> { Deg(self.pitch) }
+
+    pub fn projection(&self, width: u32, height: u32) -> Projection
+    {
+        match self.projection
+        {
+            ProjectionDesc::Perspective { fovy, znear, zfar } => Projection::new(width, height, Deg(fovy), znear, zfar),
+            ProjectionDesc::Orthographic { zoom, znear, zfar } => Projection::orthographic(width, height, zoom, znear, zfar),
+        }
+    }
+}
+
+
+// keyframes are (time, value, easing)
+pub type Keys<T> = Vec<(f32, T, Easing)>;
+
+
+#[derive(Debug, Deserialize)]
+pub struct AnimationDesc
+{
+    pub name: String,
+    pub playback: Playback,
+    // waits to be restarted, for animations started from input
+    #[serde(default)]
+    pub paused: bool,
+    pub target: TargetDesc,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub enum TargetDesc
+{
+    // a scene graph node by instance name, rotations are axis and angle in degrees
+    Node
+    {
+        node: String,
+        #[serde(default)]
+        position: Keys<[f32; 3]>,
+        #[serde(default)]
+        rotation: Keys<([f32; 3], f32)>,
+        #[serde(default)]
+        scale: Keys<[f32; 3]>,
+    },
+    // look_at keys are (time, eye, target, easing), fovy in degrees
+    Camera
+    {
+        #[serde(default)]
+        look_at: Vec<(f32, [f32; 3], [f32; 3], Easing)>,
+        #[serde(default)]
+        fovy: Keys<f32>,
+    },
+    // material indexes the flattened material list
+    Material
+    {
+        material: u32,
+        #[serde(default)]
+        color: Keys<[f32; 4]>,
+        #[serde(default)]
+        floats: Vec<(MaterialFloat, Keys<f32>)>,
+    },
+}
+
+
+fn track<D: Copy, T: Animatable>(keys: &[(f32, D, Easing)], value: impl Fn(D) -> T) -> Track<T>
+{
+    keys.iter().fold(Track::new(), |track, &(time, v, easing)| track.key(time, value(v), easing))
+}
+
+
+impl AnimationDesc
+{
+    // the node has to exist in graph, SceneDesc::validate checks it
+    pub fn to_animation(&self, graph: &SceneGraph) -> Animation
+    {
+        let target = match &self.target
+        {
+            TargetDesc::Node { node, position, rotation, scale } => Target::Node(
+                graph.find(node).expect("animated node was validated"),
+                TransformAnimation
+                {
+                    position: track(position, Vector3::from),
+                    rotation: track(rotation, axis_angle),
+                    scale: track(scale, Vector3::from),
+                },
+            ),
+            TargetDesc::Camera { look_at, fovy } =>
+            {
+                let camera = look_at.iter().fold(CameraAnimation::default(), |camera, &(time, eye, target, easing)|
+                    camera.look_at(time, eye.into(), target.into(), easing));
+                Target::Camera(CameraAnimation { fovy: track(fovy, |f| Rad::from(Deg(f))), ..camera })
+            }
+            TargetDesc::Material { material, color, floats } => Target::Material(
+                *material as usize,
+                MaterialAnimation
+                {
+                    color: track(color, |color| Color { color }),
+                    floats: floats.iter().map(|(float, keys)| (*float, track(keys, |f| f))).collect(),
+                },
+            ),
+        };
+        let mut clock = Clock::new(target.duration(), self.playback);
+        clock.playing = !self.paused;
+        Animation { name: self.name.clone(), target, clock }
+    }
+}
+// }}}
+
+
+
+
+// SCENE {{{
+pub struct Scene
+{
+    pub materials: Vec<Material>,
+    pub models: Vec<(Model, Instances)>,
+    // instances of every model, Instances above are packed from it
+    pub graph: SceneGraph,
+    // skeleton and clips of every .gltf model, by model name
+    pub rigs: Vec<(String, Skeleton, Vec<Clip>)>,
+    pub animations: Vec<Animation>,
+    pub lights: Vec<Light>,
+    pub camera: CameraDesc,
+}
+
+
+impl Scene
+{
+    // moves a model out of the scene, for code that keeps named fields
+    pub fn take(&mut self, name: &str) -> Option<(Model, Instances)>
+    {
+        let i = self.models.iter().position(|(model, _)| model.name == name)?;
+        Some(self.models.remove(i))
+    }
+
+    pub fn take_rig(&mut self, name: &str) -> Option<(Skeleton, Vec<Clip>)>
+    {
+        let i = self.rigs.iter().position(|(model, _, _)| model == name)?;
+        let (_, skeleton, clips) = self.rigs.remove(i);
+        Some((skeleton, clips))
+    }
+}
+
+
+pub async fn load_scene(
+    file_name: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,) -> anyhow::Result<Scene>
+{
+    let text = assets::load_string(file_name).await?;
+    let desc: SceneDesc = ron::from_str(&text)?;
+
+    let mut materials = Vec::new();
+    for m in &desc.materials
+    {
+        match m
+        {
+            MaterialDesc::Mtl(file) => materials.extend(assets::load_materials(file, device, queue, layout).await?),
+            MaterialDesc::Color { name, color, texture } =>
+            {
+                let diffuse_texture = match texture
+                {
+                    Some(file) => assets::load_texture(file, device, queue).await?,
+                    None => Texture::default_white(device, queue),
+                };
+                materials.push(Material::new(device, layout, name, diffuse_texture, Color { color: *color }));
+            }
+        }
+    }
+
+    desc.validate(materials.len()).map_err(|e| anyhow::anyhow!("{}: {}", file_name, e))?;
+
+    let mut graph = SceneGraph::new();
+    for m in &desc.models
+    {
+        for instance in &m.instances
+        {
+            instance.add_to(&mut graph, &m.name, None);
+        }
+    }
+    graph.update();
+    let animations = desc.animations.iter().map(|a| a.to_animation(&graph)).collect();
+
+    let mut models = Vec::new();
+    let mut rigs = Vec::new();
+    for m in desc.models
+    {
+        let mut meshes = if m.file.ends_with(".gltf")
+        {
+            let (meshes, skeleton, clips) = assets::load_gltf(&m.file, device).await?;
+            rigs.push((m.name.clone(), skeleton, clips));
+            meshes
+        }
+        else
+        {
+            assets::load_model(&m.file, device, queue, layout).await?.0
+        };
+        for mesh in &mut meshes
+        {
+            mesh.generate_lods(device, &m.lods);
+        }
+        let instances = graph.create_instances(device, &m.name);
+        models.push((Model { name: m.name, meshes, materials: m.materials }, instances));
+    }
+
+    Ok(Scene
+    {
+        materials,
+        models,
+        graph,
+        rigs,
+        animations,
+        lights: desc.lights,
+        camera: desc.camera,
+    })
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const CAMERA: &str = "camera: (position: (0.0, 0.0, 0.0), yaw: 0.0, pitch: 0.0, projection: Perspective(fovy: 45.0, znear: 0.1, zfar: 100.0))";
+
+    fn parse(materials: &str, models: &str) -> SceneDesc
+    {
+        ron::from_str(&format!("(materials: [{}], models: [{}], {})", materials, models, CAMERA)).unwrap()
+    }
+
+    #[test]
+    fn rejects_unknown_child_model()
+    {
+        let desc = parse("", r#"(name: "sphere", file: "sphere.obj", instances: [(children: [("moon", ())])])"#);
+        let error = desc.validate(0).unwrap_err().to_string();
+        assert!(error.contains("unknown model \"moon\""), "{}", error);
+
+        let desc = parse("", r#"(name: "sphere", file: "sphere.obj", instances: [(children: [("sphere", (scale: (0.3, 0.3, 0.3)))])])"#);
+        desc.validate(0).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_range_material()
+    {
+        let desc = parse(r#"Color(name: "red", color: (1.0, 0.0, 0.0, 1.0))"#, r#"(name: "cube", file: "cube.obj", materials: [0, 1])"#);
+        let error = desc.validate(1).unwrap_err().to_string();
+        assert!(error.contains("uses material 1 but only 1 are defined"), "{}", error);
+        desc.validate(2).unwrap();
+    }
+
+    #[test]
+    fn accepts_world()
+    {
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let desc: SceneDesc = ron::from_str(&std::fs::read_to_string(assets.join("world.ron")).unwrap()).unwrap();
+        // every newmtl of an .mtl file becomes one material
+        let material_count: usize = desc.materials.iter().map(|m| match m
+        {
+            MaterialDesc::Mtl(file) => std::fs::read_to_string(assets.join(file)).unwrap().lines().filter(|l| l.starts_with("newmtl")).count(),
+            MaterialDesc::Color { .. } => 1,
+        }).sum();
+        desc.validate(material_count).unwrap();
+    }
+
+    #[test]
+    fn rejects_animations_of_unknown_nodes_and_materials()
+    {
+        let animated = |target: &str| -> SceneDesc
+        {
+            let models = r#"(name: "sphere", file: "sphere.obj", instances: [(name: "left"), (children: [("sphere", (name: "moon"))])])"#;
+            ron::from_str(&format!("(materials: [], models: [{}], {}, animations: [(name: \"a\", playback: Loop, target: {})])", models, CAMERA, target)).unwrap()
+        };
+        for node in ["left", "sphere", "moon"]
+        {
+            animated(&format!("Node(node: \"{}\", position: [(0.0, (0.0, 0.0, 1.0), Linear)])", node)).validate(0).unwrap();
+        }
+        let error = animated(r#"Node(node: "right")"#).validate(0).unwrap_err().to_string();
+        assert!(error.contains("unknown node \"right\""), "{}", error);
+
+        let glow = animated(r#"Material(material: 1, floats: [(Opacity, [(0.0, 1.0, EaseIn)])])"#);
+        glow.validate(2).unwrap();
+        let error = glow.validate(1).unwrap_err().to_string();
+        assert!(error.contains("uses material 1 but only 1 are defined"), "{}", error);
+    }
+}
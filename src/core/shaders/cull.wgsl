@@ -0,0 +1,117 @@
+// Frustum and hi-z culling, compacts the visible instances and counts them into indirect draw arguments
+
+
+
+
+struct CullParams
+{
+    // view projection the hi-z pyramid was rendered with
+    hiz_view_proj: mat4x4<f32>,
+    planes: array<vec4<f32>, 6>,
+    // object space bounding sphere of the mesh
+    sphere: vec4<f32>,
+    count: u32,
+    plane_count: u32,
+    hiz_levels: u32,
+    reverse_z: u32,
+    hiz_size: vec2<f32>,
+}
+
+
+struct DrawArgs
+{
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+
+@group(0) @binding(0)
+var<uniform> params: CullParams;
+@group(0) @binding(1)
+var<storage, read> input: array<mat4x4<f32>>;
+@group(0) @binding(2)
+var<storage, read_write> output: array<mat4x4<f32>>;
+@group(0) @binding(3)
+var<storage, read_write> args: DrawArgs;
+@group(1) @binding(0)
+var hiz: texture_2d<f32>;
+
+
+
+
+fn is_nearer(a: f32, b: f32) -> bool
+{
+    if (params.reverse_z == 1u) { return a > b; }
+    return a < b;
+}
+
+
+fn occluded(center: vec3<f32>, radius: f32) -> bool
+{
+    var lo = vec2<f32>(1.0);
+    var hi = vec2<f32>(-1.0);
+    var nearest = select(1.0, 0.0, params.reverse_z == 1u);
+    for (var c = 0u; c < 8u; c++)
+    {
+        let corner = center + radius * vec3<f32>(
+            select(-1.0, 1.0, (c & 1u) != 0u),
+            select(-1.0, 1.0, (c & 2u) != 0u),
+            select(-1.0, 1.0, (c & 4u) != 0u),
+        );
+        let clip = params.hiz_view_proj * vec4<f32>(corner, 1.0);
+        // touches the camera plane, the projected rect is meaningless
+        if (clip.w <= 0.0) { return false; }
+        let ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc.xy);
+        hi = max(hi, ndc.xy);
+        if (is_nearer(ndc.z, nearest)) { nearest = ndc.z; }
+    }
+
+    // texel rect at level 0, y points down
+    let a = clamp(vec2<f32>(lo.x, -hi.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0)) * params.hiz_size;
+    let b = clamp(vec2<f32>(hi.x, -lo.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0)) * params.hiz_size;
+    let extent = max(b.x - a.x, b.y - a.y);
+    // at this level the rect spans at most two texels on each axis
+    let level = min(u32(ceil(log2(max(extent, 1.0)))), params.hiz_levels - 1u);
+    let dims = vec2<i32>(textureDimensions(hiz, i32(level))) - 1;
+    let scale = f32(1u << level);
+    let t0 = clamp(vec2<i32>(a / scale), vec2<i32>(0), dims);
+    let t1 = clamp(vec2<i32>(b / scale), vec2<i32>(0), dims);
+
+    var farthest = textureLoad(hiz, t0, i32(level)).r;
+    for (var i = 1u; i < 4u; i++)
+    {
+        let t = vec2<i32>(select(t0.x, t1.x, (i & 1u) != 0u), select(t0.y, t1.y, (i & 2u) != 0u));
+        let d = textureLoad(hiz, t, i32(level)).r;
+        if (is_nearer(farthest, d)) { farthest = d; }
+    }
+    return is_nearer(farthest, nearest);
+}
+
+
+
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>)
+{
+    let i = id.x;
+    if (i >= params.count) { return; }
+
+    let model = input[i];
+    let center = (model * vec4<f32>(params.sphere.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = params.sphere.w * scale;
+
+    for (var p = 0u; p < params.plane_count; p++)
+    {
+        let plane = params.planes[p];
+        if (dot(plane.xyz, center) + plane.w < -radius) { return; }
+    }
+    if (params.hiz_levels > 0u && occluded(center, radius)) { return; }
+
+    let slot = atomicAdd(&args.instance_count, 1u);
+    output[slot] = model;
+}
@@ -0,0 +1,49 @@
+// colored world space lines
+
+
+
+
+struct CameraUniform
+{
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+};
+
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+
+
+
+struct VertexInput
+{
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+
+
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    out.clip_position = camera.proj * camera.view * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>
+{
+    return in.color;
+}
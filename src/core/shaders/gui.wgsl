@@ -0,0 +1,82 @@
+// egui meshes in points with gamma encoded, premultiplied vertex colors
+
+
+
+
+struct Screen
+{
+    size: vec2<f32>,
+}
+
+
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+@group(1) @binding(0)
+var t_gui: texture_2d<f32>;
+@group(1) @binding(1)
+var s_gui: sampler;
+
+
+
+
+struct VertexInput
+{
+    @location(0) position: vec2<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+
+fn linear_from_gamma(srgb: vec3<f32>) -> vec3<f32>
+{
+    let lower = srgb / 12.92;
+    let higher = pow((srgb + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(higher, lower, srgb < vec3<f32>(0.04045));
+}
+
+
+fn gamma_from_linear(rgb: vec3<f32>) -> vec3<f32>
+{
+    let lower = rgb * 12.92;
+    let higher = 1.055 * pow(rgb, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(higher, lower, rgb < vec3<f32>(0.0031308));
+}
+
+
+
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    let ndc = in.position / screen.size * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    return out;
+}
+
+
+// textures are srgb so samples come out linear
+@fragment
+fn fs_linear(in: VertexOutput) -> @location(0) vec4<f32>
+{
+    let color = vec4<f32>(linear_from_gamma(in.color.rgb), in.color.a);
+    return color * textureSample(t_gui, s_gui, in.uv);
+}
+
+
+@fragment
+fn fs_gamma(in: VertexOutput) -> @location(0) vec4<f32>
+{
+    let texture = textureSample(t_gui, s_gui, in.uv);
+    return in.color * vec4<f32>(gamma_from_linear(texture.rgb), texture.a);
+}
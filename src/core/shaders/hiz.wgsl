@@ -0,0 +1,62 @@
+// Hierarchical depth, every texel keeps the farthest depth of the texels it covers one level down
+
+
+
+
+struct HiZParams
+{
+    reverse_z: u32,
+}
+
+
+// bound as unfilterable float, depth textures can not be loaded from on every backend
+@group(0) @binding(0)
+var depth: texture_2d<f32>;
+@group(0) @binding(1)
+var dst: texture_storage_2d<r32float, write>;
+@group(0) @binding(2)
+var<uniform> params: HiZParams;
+
+@group(1) @binding(0)
+var src: texture_2d<f32>;
+
+
+
+
+fn farthest(a: f32, b: f32) -> f32
+{
+    if (params.reverse_z == 1u) { return min(a, b); }
+    return max(a, b);
+}
+
+
+
+
+@compute @workgroup_size(8, 8)
+fn cs_copy(@builtin(global_invocation_id) id: vec3<u32>)
+{
+    if (any(id.xy >= textureDimensions(dst))) { return; }
+    textureStore(dst, id.xy, vec4<f32>(textureLoad(depth, id.xy, 0).r, 0.0, 0.0, 0.0));
+}
+
+
+@compute @workgroup_size(8, 8)
+fn cs_down(@builtin(global_invocation_id) id: vec3<u32>)
+{
+    let size = textureDimensions(dst);
+    if (any(id.xy >= size)) { return; }
+    let src_size = vec2<i32>(textureDimensions(src));
+
+    // odd sizes fold the leftover row and column into the last texel
+    let extra = select(vec2<i32>(0), vec2<i32>(1), (src_size % 2 == vec2<i32>(1)) & (id.xy == size - 1u));
+    let base = vec2<i32>(id.xy) * 2;
+    var d = select(0.0, 1.0, params.reverse_z == 1u);
+    for (var y = 0; y < 2 + extra.y; y++)
+    {
+        for (var x = 0; x < 2 + extra.x; x++)
+        {
+            d = farthest(d, textureLoad(src, min(base + vec2<i32>(x, y), src_size - 1), 0).r);
+        }
+    }
+    textureStore(dst, id.xy, vec4<f32>(d, 0.0, 0.0, 0.0));
+}
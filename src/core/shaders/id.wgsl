@@ -0,0 +1,68 @@
+// Id pass, writes (model << 16 | instance) + 1 for every covered pixel
+
+
+
+
+struct CameraUniform 
+{
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+};
+
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(1) @binding(0)
+var<uniform> model_id: u32;
+
+
+
+
+
+struct VertexInput 
+{
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct InstanceInput 
+{
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+}
+
+
+
+
+@vertex
+fn vs_main( model: VertexInput, instance: InstanceInput, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );    
+
+    var out: VertexOutput;
+    out.clip_position =  camera.proj * camera.view * model_matrix *  vec4<f32>(model.position, 1.0);
+    out.id = ((model_id << 16u) | instance_index) + 1u;
+    return out;
+}
+
+
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 
+{
+    return in.id;
+}
@@ -0,0 +1,63 @@
+// glyph quads in window pixels sampling the glyph atlas
+
+
+
+
+struct TextParams
+{
+    color: vec4<f32>,
+    screen: vec2<f32>,
+    sdf: u32,
+}
+
+
+@group(0) @binding(0)
+var t_atlas: texture_2d<f32>;
+@group(0) @binding(1)
+var s_atlas: sampler;
+@group(0) @binding(2)
+var<uniform> params: TextParams;
+
+
+
+
+struct VertexInput
+{
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+}
+
+
+struct VertexOutput
+{
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+
+
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput
+{
+    var out: VertexOutput;
+    let ndc = in.position.xy / params.screen * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = in.uv;
+    return out;
+}
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>
+{
+    let value = textureSample(t_atlas, s_atlas, in.uv).r;
+    // the distance field edge is at 0.5, antialiased over one screen pixel
+    let width = max(fwidth(value), 0.0001);
+    var alpha = value;
+    if params.sdf == 1u
+    {
+        alpha = smoothstep(0.5 - width, 0.5 + width, value);
+    }
+    return vec4<f32>(params.color.rgb, params.color.a * alpha);
+}
@@ -0,0 +1,488 @@
+use crate::core::renderer::BindGroupLayouts;
+
+use std::collections::HashMap;
+use std::ops::{Add, Mul};
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Quaternion, Vector3};
+use wgpu::util::DeviceExt;
+
+
+
+
+// POSE {{{
+// local transform of a joint relative to its parent
+#[derive(Debug, Clone, Copy)]
+pub struct Transform
+{
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+
+impl Transform
+{
+    pub fn to_matrix(&self) -> Matrix4<f32>
+    {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // weight 0 keeps self, 1 gives other
+    pub fn blend(&self, other: &Self, weight: f32) -> Self
+    {
+        Self
+        {
+            translation: self.translation.lerp(other.translation, weight),
+            rotation: slerp(self.rotation, other.rotation, weight),
+            scale: self.scale.lerp(other.scale, weight),
+        }
+    }
+}
+
+
+impl Default for Transform
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+
+// q and -q are the same rotation, take the short way round
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32>
+{
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    a.slerp(b, t)
+}
+
+
+// one transform per joint of a skeleton
+#[derive(Debug, Clone)]
+pub struct Pose
+{
+    pub joints: Vec<Transform>,
+}
+
+
+impl Pose
+{
+    pub fn blend(&self, other: &Pose, weight: f32) -> Pose
+    {
+        Pose
+        {
+            joints: self.joints.iter().zip(&other.joints).map(|(a, b)| a.blend(b, weight)).collect(),
+        }
+    }
+}
+// }}}
+
+
+
+
+// SKELETON {{{
+#[derive(Debug, Clone)]
+pub struct Joint
+{
+    pub name: String,
+    pub parent: Option<usize>,
+    // takes a vertex from model space into the joint's space in the bind pose
+    pub inverse_bind: Matrix4<f32>,
+    pub rest: Transform,
+}
+
+
+pub struct Skeleton
+{
+    pub joints: Vec<Joint>,
+    // joint indices with every parent ahead of its children
+    order: Vec<usize>,
+}
+
+
+impl Skeleton
+{
+    pub fn new(joints: Vec<Joint>) -> Self
+    {
+        let depth = |mut j: usize| {
+            let mut d = 0;
+            while let Some(p) = joints[j].parent
+            {
+                j = p;
+                d += 1;
+            }
+            d
+        };
+        let mut order: Vec<usize> = (0..joints.len()).collect();
+        order.sort_by_key(|&j| depth(j));
+        Self { joints, order }
+    }
+
+
+    // joints are the nodes listed by the skin, their parents are the nearest node that is a joint
+    // as well. Transforms of nodes above the skeleton root are not applied.
+    pub fn from_gltf(skin: &gltf::Skin, buffers: &[Vec<u8>]) -> Self
+    {
+        let index: HashMap<usize, usize> = skin.joints().enumerate().map(|(i, node)| (node.index(), i)).collect();
+        let mut parents = vec![None; index.len()];
+        for (i, node) in skin.joints().enumerate()
+        {
+            for child in node.children()
+            {
+                if let Some(&c) = index.get(&child.index()) { parents[c] = Some(i); }
+            }
+        }
+
+        let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+        let mut inverse_binds = reader.read_inverse_bind_matrices().map(|m| m.map(Matrix4::from));
+        let joints = skin.joints().enumerate().map(|(i, node)| {
+            let (t, [x, y, z, w], s) = node.transform().decomposed();
+            Joint
+            {
+                name: node.name().map(str::to_string).unwrap_or_else(|| format!("joint {}", i)),
+                parent: parents[i],
+                inverse_bind: inverse_binds.as_mut().and_then(Iterator::next).unwrap_or_else(Matrix4::identity),
+                rest: Transform
+                {
+                    translation: t.into(),
+                    rotation: Quaternion::new(w, x, y, z),
+                    scale: s.into(),
+                },
+            }
+        }).collect();
+        Self::new(joints)
+    }
+
+
+    pub fn find(&self, name: &str) -> Option<usize>
+    {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+
+    pub fn rest_pose(&self) -> Pose
+    {
+        Pose { joints: self.joints.iter().map(|j| j.rest).collect() }
+    }
+
+
+    // model space transform of every joint
+    pub fn world_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>>
+    {
+        let mut world = vec![Matrix4::identity(); self.joints.len()];
+        for &j in &self.order
+        {
+            let local = pose.joints[j].to_matrix();
+            world[j] = match self.joints[j].parent
+            {
+                Some(p) => world[p] * local,
+                None => local,
+            };
+        }
+        world
+    }
+
+
+    // what the skinning shader multiplies bind pose vertices with
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<[[f32; 4]; 4]>
+    {
+        self.world_matrices(pose).iter()
+            .zip(&self.joints)
+            .map(|(world, joint)| (world * joint.inverse_bind).into())
+            .collect()
+    }
+}
+// }}}
+
+
+
+
+// CLIPS {{{
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation
+{
+    Step,
+    Linear,
+    // keyframes hold an in tangent, the value and an out tangent for every time
+    CubicSpline,
+}
+
+
+#[derive(Debug, Clone)]
+pub enum Keyframes
+{
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Channel
+{
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+}
+
+
+// morph target weights of the animated mesh, one list of keyframes per target
+#[derive(Debug, Clone)]
+pub struct MorphChannel
+{
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub targets: Vec<Vec<f32>>,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Clip
+{
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+    // files are expected to morph a single mesh, further weight channels are dropped
+    pub morph: Option<MorphChannel>,
+}
+
+
+impl Clip
+{
+    // transform channels on nodes outside the skin are skipped
+    pub fn from_gltf(animation: &gltf::Animation, skin: Option<&gltf::Skin>, buffers: &[Vec<u8>]) -> Self
+    {
+        use gltf::animation::util::ReadOutputs;
+
+        let index: HashMap<usize, usize> = skin.iter()
+            .flat_map(|skin| skin.joints().enumerate().map(|(i, node)| (node.index(), i)))
+            .collect();
+        let mut channels = Vec::new();
+        let mut morph = None;
+        for channel in animation.channels()
+        {
+            let reader = channel.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let (Some(times), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else { continue };
+            let times: Vec<f32> = times.collect();
+            if times.is_empty() { continue; }
+            let interpolation = match channel.sampler().interpolation()
+            {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+
+            // weights come flat, all targets of one keyframe after another
+            if let ReadOutputs::MorphTargetWeights(w) = outputs
+            {
+                if morph.is_some() { continue; }
+                let flat: Vec<f32> = w.into_f32().collect();
+                let per_time = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+                let count = flat.len() / (times.len() * per_time);
+                let targets = (0..count)
+                    .map(|t| flat.iter().skip(t).step_by(count.max(1)).copied().collect())
+                    .collect();
+                morph = Some(MorphChannel { interpolation, times, targets });
+                continue;
+            }
+
+            let Some(&joint) = index.get(&channel.target().node().index()) else { continue };
+            let keyframes = match outputs
+            {
+                ReadOutputs::Translations(t) => Keyframes::Translation(t.map(Vector3::from).collect()),
+                ReadOutputs::Rotations(r) => Keyframes::Rotation(
+                    r.into_f32().map(|[x, y, z, w]| Quaternion::new(w, x, y, z)).collect()
+                ),
+                ReadOutputs::Scales(s) => Keyframes::Scale(s.map(Vector3::from).collect()),
+                ReadOutputs::MorphTargetWeights(_) => continue,
+            };
+            channels.push(Channel { joint, interpolation, times, keyframes });
+        }
+
+        let duration = channels.iter().map(|c| &c.times)
+            .chain(morph.as_ref().map(|m| &m.times))
+            .filter_map(|times| times.last().copied())
+            .fold(0.0, f32::max);
+        Self
+        {
+            name: animation.name().unwrap_or("animation").to_string(),
+            duration,
+            channels,
+            morph,
+        }
+    }
+
+
+    // joints without a channel keep their rest transform, time wraps around the duration
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Pose
+    {
+        let mut pose = skeleton.rest_pose();
+        self.apply(&mut pose, time);
+        pose
+    }
+
+
+    // overwrites the animated weights, targets the clip does not know keep their value
+    pub fn sample_weights(&self, time: f32, weights: &mut [f32])
+    {
+        let Some(morph) = &self.morph else { return };
+        let time = self.wrap(time);
+        for (w, values) in weights.iter_mut().zip(&morph.targets)
+        {
+            *w = sample(&morph.times, values, morph.interpolation, time, |a, b, t| a + (b - a) * t);
+        }
+    }
+
+
+    fn wrap(&self, time: f32) -> f32
+    {
+        if self.duration > 0.0 { time.rem_euclid(self.duration) } else { 0.0 }
+    }
+
+
+    // overwrites the animated parts of pose
+    pub fn apply(&self, pose: &mut Pose, time: f32)
+    {
+        let time = self.wrap(time);
+        for channel in &self.channels
+        {
+            let Some(joint) = pose.joints.get_mut(channel.joint) else { continue };
+            let (times, interpolation) = (&channel.times, channel.interpolation);
+            match &channel.keyframes
+            {
+                Keyframes::Translation(v) =>
+                    joint.translation = sample(times, v, interpolation, time, |a, b, t| a.lerp(b, t)),
+                Keyframes::Rotation(v) =>
+                    joint.rotation = sample(times, v, interpolation, time, slerp).normalize(),
+                Keyframes::Scale(v) =>
+                    joint.scale = sample(times, v, interpolation, time, |a, b, t| a.lerp(b, t)),
+            }
+        }
+    }
+}
+
+
+// Hermite spline for cubic keyframes, tangents are scaled by the time between the two keys
+fn sample<T>(times: &[f32], values: &[T], interpolation: Interpolation, time: f32, lerp: impl Fn(T, T, f32) -> T) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let cubic = interpolation == Interpolation::CubicSpline;
+    let value = |k: usize| if cubic { values[k * 3 + 1] } else { values[k] };
+    let last = times.len() - 1;
+    if time <= times[0] { return value(0); }
+    if time >= times[last] { return value(last); }
+
+    let k = times.partition_point(|&t| t <= time) - 1;
+    let dt = times[k + 1] - times[k];
+    let s = (time - times[k]) / dt;
+    match interpolation
+    {
+        Interpolation::Step => value(k),
+        Interpolation::Linear => lerp(value(k), value(k + 1), s),
+        Interpolation::CubicSpline =>
+        {
+            let (s2, s3) = (s * s, s * s * s);
+            let out_tangent = values[k * 3 + 2];
+            let in_tangent = values[(k + 1) * 3];
+            value(k) * (2.0 * s3 - 3.0 * s2 + 1.0)
+                + out_tangent * (dt * (s3 - 2.0 * s2 + s))
+                + value(k + 1) * (3.0 * s2 - 2.0 * s3)
+                + in_tangent * (dt * (s3 - s2))
+        }
+    }
+}
+// }}}
+
+
+
+
+// SKIN {{{
+// joint matrices of one skeleton on the gpu, bound as PipelineResources::Skin
+pub struct Skin
+{
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+
+impl Skin
+{
+    pub fn new(device: &wgpu::Device, layouts: &BindGroupLayouts, skeleton: &Skeleton) -> Self
+    {
+        let mut matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        // empty storage bindings are not allowed
+        if matrices.is_empty() { matrices.push(Matrix4::identity().into()); }
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Skin Buffer"),
+            contents: bytemuck::cast_slice(&matrices),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.skin,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+            label: Some("skin_bind_group"),
+        });
+        Self { buffer, bind_group }
+    }
+
+
+    pub fn update(&self, queue: &wgpu::Queue, skeleton: &Skeleton, pose: &Pose)
+    {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&skeleton.joint_matrices(pose)));
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn cubic_spline_clip_follows_the_hermite_curve()
+    {
+        let skeleton = Skeleton::new(vec![Joint
+        {
+            name: "root".to_string(),
+            parent: None,
+            inverse_bind: Matrix4::identity(),
+            rest: Transform::default(),
+        }]);
+        let x = |x: f32| Vector3::new(x, 0.0, 0.0);
+        // in tangent, value, out tangent for keys at 0 and 2 seconds, leaving the first key at one unit a second
+        let clip = Clip
+        {
+            name: "slide".to_string(),
+            duration: 2.0,
+            channels: vec![Channel
+            {
+                joint: 0,
+                interpolation: Interpolation::CubicSpline,
+                times: vec![0.0, 2.0],
+                keyframes: Keyframes::Translation(vec![x(5.0), x(0.0), x(1.0), x(0.0), x(1.0), x(5.0)]),
+            }],
+            morph: None,
+        };
+        let at = |time: f32| clip.sample(&skeleton, time).joints[0].translation.x;
+        // on the keys the value comes out, not a tangent
+        assert_eq!(at(0.0), 0.0);
+        assert_eq!(at(2.0), 0.0);
+        assert!((at(1.999) - 1.0).abs() < 1e-2);
+        // s = 0.25: h01 = 0.15625 and h10 * dt = 0.140625 * 2
+        assert!((at(0.5) - 0.4375).abs() < 1e-5, "{}", at(0.5));
+        // s = 0.5: h01 = 0.5 and h10 * dt = 0.125 * 2
+        assert!((at(1.0) - 0.75).abs() < 1e-5, "{}", at(1.0));
+    }
+}
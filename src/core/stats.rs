@@ -0,0 +1,520 @@
+use instant::Duration;
+use std::sync::mpsc;
+
+
+
+
+// HISTOGRAM {{{
+// the last capacity samples of one timing, in milliseconds
+#[derive(Debug, Clone)]
+pub struct Histogram
+{
+    samples: Vec<f32>,
+    next: usize,
+    capacity: usize,
+}
+
+
+impl Histogram
+{
+    pub fn new(capacity: usize) -> Self
+    {
+        Self { samples: Vec::with_capacity(capacity), next: 0, capacity: capacity.max(1) }
+    }
+
+    pub fn push(&mut self, time: Duration)
+    {
+        let ms = time.as_secs_f32() * 1000.0;
+        if self.samples.len() < self.capacity { self.samples.push(ms); }
+        else { self.samples[self.next] = ms; }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    pub fn len(&self) -> usize { self.samples.len() }
+    pub fn is_empty(&self) -> bool { self.samples.is_empty() }
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    // oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = f32> + '_
+    {
+        let split = if self.samples.len() < self.capacity { 0 } else { self.next };
+        self.samples[split..].iter().chain(&self.samples[..split]).copied()
+    }
+
+    pub fn last(&self) -> f32
+    {
+        self.iter().next_back().unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f32
+    {
+        if self.samples.is_empty() { return 0.0; }
+        self.samples.iter().sum::<f32>() / self.samples.len() as f32
+    }
+
+    pub fn max(&self) -> f32
+    {
+        self.samples.iter().copied().fold(0.0, f32::max)
+    }
+
+    // nearest rank, p in 0..=100
+    pub fn percentile(&self, p: f32) -> f32
+    {
+        if self.samples.is_empty() { return 0.0; }
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(f32::total_cmp);
+        let rank = (p / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+
+    // sample counts in buckets of width milliseconds, the last bucket takes everything slower
+    pub fn buckets(&self, width: f32, count: usize) -> Vec<u32>
+    {
+        let mut buckets = vec![0; count.max(1)];
+        let last = buckets.len() - 1;
+        for ms in &self.samples
+        {
+            buckets[((ms / width) as usize).min(last)] += 1;
+        }
+        buckets
+    }
+}
+
+
+// where the time of a frame went, frame is the whole time between two redraws
+#[derive(Debug, Clone)]
+pub struct FrameStats
+{
+    pub frame: Histogram,
+    pub update: Histogram,
+    pub encode: Histogram,
+    pub present: Histogram,
+    // gpu time of each timed pass, filled in by GpuTimer a few frames late
+    pub gpu: Vec<(&'static str, Histogram)>,
+}
+
+
+impl FrameStats
+{
+    pub fn new(capacity: usize) -> Self
+    {
+        Self
+        {
+            frame: Histogram::new(capacity),
+            update: Histogram::new(capacity),
+            encode: Histogram::new(capacity),
+            present: Histogram::new(capacity),
+            gpu: Vec::new(),
+        }
+    }
+
+    pub fn gpu_pass(&mut self, name: &'static str) -> &mut Histogram
+    {
+        let i = match self.gpu.iter().position(|(n, _)| *n == name)
+        {
+            Some(i) => i,
+            None =>
+            {
+                self.gpu.push((name, Histogram::new(self.frame.capacity())));
+                self.gpu.len() - 1
+            }
+        };
+        &mut self.gpu[i].1
+    }
+
+    pub fn fps(&self) -> f32
+    {
+        let mean = self.frame.mean();
+        if mean > 0.0 { 1000.0 / mean } else { 0.0 }
+    }
+
+    pub fn summary(&self) -> String
+    {
+        let line = |name: &str, h: &Histogram| format!("{:8} mean {:6.2}  p50 {:6.2}  p95 {:6.2}  p99 {:6.2}  max {:6.2} ms",
+            name, h.mean(), h.percentile(50.0), h.percentile(95.0), h.percentile(99.0), h.max());
+        let mut summary = format!("{:.1} fps over {} frames\n{}\n{}\n{}\n{}", self.fps(), self.frame.len(),
+            line("frame", &self.frame), line("update", &self.update), line("encode", &self.encode), line("present", &self.present));
+        for (name, h) in &self.gpu
+        {
+            summary += &format!("\n{}", line(&format!("gpu {}", name), h));
+        }
+        summary
+    }
+}
+// }}}
+
+
+
+
+// OVERLAY {{{
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayVertex
+{
+    // clip space
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+
+// one column per frame, from the bottom: update, encode, present and the rest of the frame
+const OVERLAY_BAR: f32 = 2.0;
+const OVERLAY_HEIGHT: f32 = 100.0;
+const OVERLAY_MARGIN: f32 = 8.0;
+// milliseconds at the top of the graph
+const OVERLAY_SCALE: f32 = 50.0;
+const OVERLAY_COLORS: [[f32; 4]; 4] = [
+    [0.2, 0.8, 0.3, 0.9],
+    [0.2, 0.5, 1.0, 0.9],
+    [1.0, 0.6, 0.1, 0.9],
+    [0.5, 0.5, 0.5, 0.6],
+];
+
+
+// a frame time graph drawn over whatever is in the target
+pub struct StatsOverlay
+{
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    vertices: Vec<OverlayVertex>,
+    capacity: usize,
+    pub visible: bool,
+}
+
+
+impl StatsOverlay
+{
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, history: usize) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/overlay.wgsl"));
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("overlay_pipeline_layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("overlay_pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        // background, four segments per column and three marker lines
+        let capacity = (1 + history * 4 + 3) * 6;
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("overlay_vertex_buffer"),
+            size: (capacity * std::mem::size_of::<OverlayVertex>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self { pipeline, vertex_buffer, vertices: Vec::with_capacity(capacity), capacity, visible: true }
+    }
+
+
+    // rebuilds the graph for a target of width by height pixels
+    pub fn update(&mut self, queue: &wgpu::Queue, stats: &FrameStats, width: u32, height: u32)
+    {
+        self.vertices.clear();
+        if !self.visible || width == 0 || height == 0 { return; }
+
+        let (w, h) = (width as f32, height as f32);
+        let vertices = &mut self.vertices;
+        // a rectangle in pixels from the bottom left corner of the graph
+        let mut rect = |x: f32, y: f32, dx: f32, dy: f32, color: [f32; 4]|
+        {
+            let x0 = (OVERLAY_MARGIN + x) / w * 2.0 - 1.0;
+            let x1 = (OVERLAY_MARGIN + x + dx) / w * 2.0 - 1.0;
+            let y0 = (OVERLAY_MARGIN + y) / h * 2.0 - 1.0;
+            let y1 = (OVERLAY_MARGIN + y + dy) / h * 2.0 - 1.0;
+            for (px, py) in [(x0, y0), (x1, y0), (x1, y1), (x0, y0), (x1, y1), (x0, y1)]
+            {
+                vertices.push(OverlayVertex { position: [px, py], color });
+            }
+        };
+
+        let columns = stats.frame.capacity().min((self.capacity / 6 - 4) / 4);
+        let graph_width = columns as f32 * OVERLAY_BAR;
+        let scale = OVERLAY_HEIGHT / OVERLAY_SCALE;
+        rect(0.0, 0.0, graph_width, OVERLAY_HEIGHT, [0.0, 0.0, 0.0, 0.5]);
+
+        // newest frame on the right, lined up from the newest end in case a frame missed a timing
+        let parts = stats.update.iter().rev().zip(stats.encode.iter().rev()).zip(stats.present.iter().rev()).zip(stats.frame.iter().rev());
+        for (i, (((update, encode), present), frame)) in parts.take(columns).enumerate()
+        {
+            let x = graph_width - (i + 1) as f32 * OVERLAY_BAR;
+            let rest = (frame - update - encode - present).max(0.0);
+            let mut y = 0.0;
+            for (ms, color) in [update, encode, present, rest].into_iter().zip(OVERLAY_COLORS)
+            {
+                let dy = (ms * scale).min(OVERLAY_HEIGHT - y);
+                rect(x, y, OVERLAY_BAR, dy, color);
+                y += dy;
+            }
+        }
+
+        // 60 and 30 fps, and the 99th percentile frame
+        for (ms, color) in [(1000.0 / 60.0, [1.0, 1.0, 1.0, 0.4]), (1000.0 / 30.0, [1.0, 1.0, 1.0, 0.4]), (stats.frame.percentile(99.0), [1.0, 0.2, 0.2, 0.8])]
+        {
+            rect(0.0, (ms * scale).min(OVERLAY_HEIGHT - 1.0), graph_width, 1.0, color);
+        }
+
+        self.vertices.truncate(self.capacity);
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+    }
+
+
+    // draws on top of view, keeping what is there
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView)
+    {
+        if self.vertices.is_empty() { return; }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertices.len() as u32, 0..1);
+    }
+}
+// }}}
+
+
+
+
+// GPU TIMER {{{
+// frames of timestamps that can be on their way back at once
+const GPU_TIMER_READBACKS: usize = 3;
+
+
+struct Readback
+{
+    buffer: wgpu::Buffer,
+    // the timed passes in query order, empty while the buffer is free
+    passes: Vec<&'static str>,
+    // the map_async result, Some from map until poll has seen it
+    mapping: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+
+// begin and end timestamps around render passes, read back frames later so nothing waits on the
+// gpu. without TIMESTAMP_QUERY every call does nothing
+pub struct GpuTimer
+{
+    query_set: Option<wgpu::QuerySet>,
+    resolve_buffer: Option<wgpu::Buffer>,
+    readbacks: Vec<Readback>,
+    passes: Vec<&'static str>,
+    max_passes: u32,
+    // nanoseconds per timestamp tick
+    period: f32,
+}
+
+
+impl GpuTimer
+{
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, max_passes: u32) -> Self
+    {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            log::info!("gpu timer: no timestamp queries on this adapter");
+            return Self { query_set: None, resolve_buffer: None, readbacks: Vec::new(), passes: Vec::new(), max_passes, period: 1.0 };
+        }
+
+        let size = max_passes as u64 * 2 * std::mem::size_of::<u64>() as u64;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("gpu_timer_queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: max_passes * 2,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_timer_resolve"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..GPU_TIMER_READBACKS).map(|_| Readback
+        {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("gpu_timer_readback"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            passes: Vec::new(),
+            mapping: None,
+        }).collect();
+
+        Self
+        {
+            query_set: Some(query_set),
+            resolve_buffer: Some(resolve_buffer),
+            readbacks,
+            passes: Vec::new(),
+            max_passes,
+            period: queue.get_timestamp_period(),
+        }
+    }
+
+    pub fn is_supported(&self) -> bool { self.query_set.is_some() }
+
+
+    // timestamp_writes for the next pass of this frame, None when unsupported or out of queries
+    pub fn pass(&mut self, name: &'static str) -> Option<wgpu::RenderPassTimestampWrites<'_>>
+    {
+        let query_set = self.query_set.as_ref()?;
+        if self.passes.len() as u32 >= self.max_passes { return None; }
+        let index = self.passes.len() as u32 * 2;
+        self.passes.push(name);
+        Some(wgpu::RenderPassTimestampWrites
+        {
+            query_set,
+            beginning_of_pass_write_index: Some(index),
+            end_of_pass_write_index: Some(index + 1),
+        })
+    }
+
+
+    // copies the frame's timestamps to a free readback buffer, with none free the frame goes untimed
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder)
+    {
+        let passes = std::mem::take(&mut self.passes);
+        let (Some(query_set), Some(resolve_buffer)) = (&self.query_set, &self.resolve_buffer) else { return };
+        if passes.is_empty() { return; }
+        let Some(readback) = self.readbacks.iter_mut().find(|r| r.passes.is_empty()) else { return };
+
+        let count = passes.len() as u32 * 2;
+        encoder.resolve_query_set(query_set, 0..count, resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(resolve_buffer, 0, &readback.buffer, 0, count as u64 * std::mem::size_of::<u64>() as u64);
+        readback.passes = passes;
+    }
+
+
+    // after submit, starts mapping what resolve copied
+    pub fn map(&mut self)
+    {
+        for readback in self.readbacks.iter_mut().filter(|r| !r.passes.is_empty() && r.mapping.is_none())
+        {
+            let (sender, receiver) = mpsc::channel();
+            readback.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| { sender.send(result).ok(); });
+            readback.mapping = Some(receiver);
+        }
+    }
+
+
+    // never blocks, moves whatever the gpu has finished into stats, a failed readback drops its frame
+    // and frees the buffer
+    pub fn poll(&mut self, device: &wgpu::Device, stats: &mut FrameStats)
+    {
+        if !self.is_supported() { return; }
+        device.poll(wgpu::Maintain::Poll);
+        for readback in &mut self.readbacks
+        {
+            let Some(receiver) = &readback.mapping else { continue };
+            let mapped = match receiver.try_recv()
+            {
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Ok(result) => result.is_ok(),
+                Err(mpsc::TryRecvError::Disconnected) => false,
+            };
+            if mapped
+            {
+                {
+                    let data = readback.buffer.slice(..).get_mapped_range();
+                    let ticks: &[u64] = bytemuck::cast_slice(&data);
+                    for (i, name) in readback.passes.iter().enumerate()
+                    {
+                        let elapsed = ticks[i * 2 + 1].saturating_sub(ticks[i * 2]) as f64 * self.period as f64;
+                        stats.gpu_pass(name).push(Duration::from_nanos(elapsed as u64));
+                    }
+                }
+                readback.buffer.unmap();
+            }
+            else
+            {
+                log::warn!("gpu timer: mapping a readback failed, dropping its frame");
+            }
+            readback.passes.clear();
+            readback.mapping = None;
+        }
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn histogram(capacity: usize, samples: impl IntoIterator<Item = u64>) -> Histogram
+    {
+        let mut histogram = Histogram::new(capacity);
+        for ms in samples { histogram.push(Duration::from_millis(ms)); }
+        histogram
+    }
+
+    #[test]
+    fn full_buffer_wraps_around_oldest_first()
+    {
+        let partial = histogram(4, [1, 2, 3]);
+        assert_eq!(partial.iter().collect::<Vec<_>>(), [1.0, 2.0, 3.0]);
+
+        let full = histogram(4, 1..=6);
+        assert_eq!(full.len(), 4);
+        assert_eq!(full.iter().collect::<Vec<_>>(), [3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(full.last(), 6.0);
+        assert_eq!(full.max(), 6.0);
+        assert_eq!(full.mean(), 4.5);
+
+        // exactly one lap puts the split back at the start
+        let lap = histogram(4, 1..=8);
+        assert_eq!(lap.iter().collect::<Vec<_>>(), [5.0, 6.0, 7.0, 8.0]);
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank()
+    {
+        assert_eq!(Histogram::new(8).percentile(50.0), 0.0);
+        // pushed out of order and wrapped, ranks only depend on the kept samples
+        let h = histogram(5, [99, 50, 30, 10, 40, 20]);
+        assert_eq!(h.percentile(0.0), 10.0);
+        assert_eq!(h.percentile(20.0), 10.0);
+        assert_eq!(h.percentile(21.0), 20.0);
+        assert_eq!(h.percentile(50.0), 30.0);
+        assert_eq!(h.percentile(80.0), 40.0);
+        assert_eq!(h.percentile(99.0), 50.0);
+        assert_eq!(h.percentile(100.0), 50.0);
+    }
+}
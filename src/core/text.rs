@@ -0,0 +1,607 @@
+use crate::core::assets;
+use crate::core::renderer::{VertexBuffer, VertexUV};
+
+use ab_glyph::{Font, FontArc, GlyphId, PxScale, ScaleFont};
+use std::collections::HashMap;
+
+
+
+
+// FONT {{{
+pub async fn load_font(file_name: &str) -> anyhow::Result<FontArc>
+{
+    let data = assets::load_binary(file_name).await?;
+    Ok(FontArc::try_from_vec(data)?)
+}
+
+
+// a glyph's pen position on the baseline, in pixels from the top left of the text
+#[derive(Debug, Clone, Copy)]
+pub struct LaidGlyph
+{
+    pub id: GlyphId,
+    pub x: f32,
+    pub y: f32,
+}
+
+
+// lines break at \n and, with a max_width, before the word that would cross it. a word wider than
+// a whole line breaks between letters. returns the glyphs and the width and height of the text
+pub fn layout(font: &FontArc, text: &str, size: f32, max_width: Option<f32>) -> (Vec<LaidGlyph>, [f32; 2])
+{
+    let scaled = font.as_scaled(PxScale::from(size));
+    let line_height = scaled.height() + scaled.line_gap();
+    let too_wide = |x: f32| max_width.is_some_and(|max| x > max);
+
+    let mut glyphs = Vec::new();
+    let mut width: f32 = 0.0;
+    let mut x = 0.0;
+    let mut y = scaled.ascent();
+    for (i, line) in text.split('\n').enumerate()
+    {
+        if i > 0
+        {
+            x = 0.0;
+            y += line_height;
+        }
+        let mut prev: Option<GlyphId> = None;
+        for word in line.split_inclusive(' ')
+        {
+            // trailing spaces may hang past the edge
+            let mut end = x;
+            let mut last = prev;
+            for c in word.trim_end_matches(' ').chars()
+            {
+                let id = font.glyph_id(c);
+                if let Some(p) = last { end += scaled.kern(p, id); }
+                end += scaled.h_advance(id);
+                last = Some(id);
+            }
+            if x > 0.0 && too_wide(end)
+            {
+                x = 0.0;
+                y += line_height;
+                prev = None;
+            }
+
+            for c in word.chars()
+            {
+                let id = font.glyph_id(c);
+                if let Some(p) = prev { x += scaled.kern(p, id); }
+                let advance = scaled.h_advance(id);
+                if x > 0.0 && c != ' ' && too_wide(x + advance)
+                {
+                    x = 0.0;
+                    y += line_height;
+                }
+                glyphs.push(LaidGlyph { id, x, y });
+                x += advance;
+                if c != ' ' { width = width.max(x); }
+                prev = Some(id);
+            }
+        }
+    }
+    (glyphs, [width, y - scaled.descent()])
+}
+// }}}
+
+
+
+
+// ATLAS {{{
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextMode
+{
+    // coverage rasterized at every size drawn, sharpest at small sizes
+    Bitmap,
+    // one signed distance field per glyph scaled to any size
+    Sdf,
+}
+
+
+// sdf glyphs are rasterized at SDF_SIZE pixels with SDF_SPREAD pixels of distance around them
+const SDF_SIZE: f32 = 32.0;
+const SDF_SPREAD: i32 = 4;
+const ATLAS_SIZE: u32 = 1024;
+
+
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph
+{
+    // min u, min v, max u, max v
+    uv: [f32; 4],
+    // top left corner from the pen and size, in pixels at the rasterized size
+    offset: [f32; 2],
+    size: [f32; 2],
+    raster: f32,
+}
+
+
+// glyphs packed on shelves into one R8 image, uploaded whenever a new one lands
+struct GlyphAtlas
+{
+    mode: TextMode,
+    pixels: Vec<u8>,
+    // glyph and rasterized size, None for glyphs without an outline like space
+    glyphs: HashMap<(GlyphId, u32), Option<AtlasGlyph>>,
+    shelf: [u32; 3],
+    dirty: bool,
+}
+
+
+impl GlyphAtlas
+{
+    fn new(mode: TextMode) -> Self
+    {
+        Self
+        {
+            mode,
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            glyphs: HashMap::new(),
+            shelf: [0, 0, 0],
+            dirty: true,
+        }
+    }
+
+
+    fn clear(&mut self)
+    {
+        self.pixels.fill(0);
+        self.glyphs.clear();
+        self.shelf = [0, 0, 0];
+        self.dirty = true;
+    }
+
+
+    // top left of a free w by h rectangle, shelves fill left to right then top to bottom
+    fn allocate(&mut self, w: u32, h: u32) -> Option<(u32, u32)>
+    {
+        let [x, y, height] = &mut self.shelf;
+        if *x + w > ATLAS_SIZE
+        {
+            *x = 0;
+            *y += *height;
+            *height = 0;
+        }
+        if *y + h > ATLAS_SIZE || w > ATLAS_SIZE { return None; }
+        let corner = (*x, *y);
+        *x += w;
+        *height = (*height).max(h);
+        Some(corner)
+    }
+
+
+    fn get(&mut self, font: &FontArc, id: GlyphId, size: f32) -> Option<AtlasGlyph>
+    {
+        let (raster, pad) = match self.mode
+        {
+            TextMode::Bitmap => (size.round().max(1.0), 1),
+            TextMode::Sdf => (SDF_SIZE, SDF_SPREAD),
+        };
+        let key = (id, raster as u32);
+        if let Some(glyph) = self.glyphs.get(&key) { return *glyph; }
+
+        let Some(outline) = font.outline_glyph(id.with_scale(raster)) else
+        {
+            self.glyphs.insert(key, None);
+            return None;
+        };
+        let bounds = outline.px_bounds();
+        let w = bounds.width().ceil() as i32 + 2 * pad;
+        let h = bounds.height().ceil() as i32 + 2 * pad;
+        let mut coverage = vec![0.0; (w * h) as usize];
+        outline.draw(|x, y, c| coverage[((y as i32 + pad) * w + x as i32 + pad) as usize] = c);
+        let values: Vec<u8> = match self.mode
+        {
+            TextMode::Bitmap => coverage.iter().map(|c| (c * 255.0) as u8).collect(),
+            TextMode::Sdf => distance_field(&coverage, w, h),
+        };
+
+        let corner = match self.allocate(w as u32, h as u32)
+        {
+            Some(corner) => corner,
+            None =>
+            {
+                // text already queued this frame may show stale glyphs for a frame
+                log::warn!("text: glyph atlas full, starting over");
+                self.clear();
+                self.allocate(w as u32, h as u32)?
+            }
+        };
+        for row in 0..h as u32
+        {
+            let start = ((corner.1 + row) * ATLAS_SIZE + corner.0) as usize;
+            self.pixels[start..start + w as usize].copy_from_slice(&values[(row * w as u32) as usize..((row + 1) * w as u32) as usize]);
+        }
+        self.dirty = true;
+
+        let atlas = ATLAS_SIZE as f32;
+        let glyph = AtlasGlyph
+        {
+            uv: [corner.0 as f32 / atlas, corner.1 as f32 / atlas, (corner.0 + w as u32) as f32 / atlas, (corner.1 + h as u32) as f32 / atlas],
+            offset: [bounds.min.x - pad as f32, bounds.min.y - pad as f32],
+            size: [w as f32, h as f32],
+            raster,
+        };
+        self.glyphs.insert(key, Some(glyph));
+        Some(glyph)
+    }
+}
+
+
+// 0.5 on the outline, rising inside, SDF_SPREAD pixels from the edge reach 0 and 1
+fn distance_field(coverage: &[f32], w: i32, h: i32) -> Vec<u8>
+{
+    let inside = |x: i32, y: i32| coverage[(y * w + x) as usize] >= 0.5;
+    let mut values = Vec::with_capacity(coverage.len());
+    for y in 0..h
+    {
+        for x in 0..w
+        {
+            let c = coverage[(y * w + x) as usize];
+            let me = c >= 0.5;
+            let distance = if c > 0.0 && c < 1.0
+            {
+                // pixels on the edge know best how far in they are
+                c - 0.5
+            }
+            else
+            {
+                let mut nearest = SDF_SPREAD as f32 + 0.5;
+                for dy in -SDF_SPREAD..=SDF_SPREAD
+                {
+                    for dx in -SDF_SPREAD..=SDF_SPREAD
+                    {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= w || ny >= h || inside(nx, ny) == me { continue; }
+                        nearest = nearest.min(((dx * dx + dy * dy) as f32).sqrt());
+                    }
+                }
+                // the edge lies between the two pixel centres
+                if me { nearest - 0.5 } else { 0.5 - nearest }
+            };
+            values.push(((0.5 + distance / (2.0 * SDF_SPREAD as f32)).clamp(0.0, 1.0) * 255.0) as u8);
+        }
+    }
+    values
+}
+// }}}
+
+
+
+
+// RENDERER {{{
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextParams
+{
+    color: [f32; 4],
+    screen: [f32; 2],
+    sdf: u32,
+    _padding: u32,
+}
+
+
+// queued strings become one batch of glyph quads in window pixels, drawn over the target
+pub struct TextRenderer
+{
+    pub font: FontArc,
+    pub color: [f32; 4],
+    atlas: GlyphAtlas,
+    texture: wgpu::Texture,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    // glyphs the buffers hold
+    capacity: usize,
+    vertices: Vec<VertexUV>,
+}
+
+
+impl TextRenderer
+{
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, font: FontArc, mode: TextMode) -> Self
+    {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/text.wgsl"));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph_atlas"),
+            size: wgpu::Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // distance fields interpolate, bitmaps are drawn texel for pixel
+        let filter = if mode == TextMode::Sdf { wgpu::FilterMode::Linear } else { wgpu::FilterMode::Nearest };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text_params"),
+            size: std::mem::size_of::<TextParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text_bind_group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&view) },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::Sampler(&sampler) },
+                wgpu::BindGroupEntry { binding: 2, resource: params_buffer.as_entire_binding() },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[VertexUV::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let capacity = 256;
+        let (vertex_buffer, index_buffer) = Self::buffers(device, capacity);
+        Self
+        {
+            font,
+            color: [1.0, 1.0, 1.0, 1.0],
+            atlas: GlyphAtlas::new(mode),
+            texture,
+            params_buffer,
+            bind_group,
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            capacity,
+            vertices: Vec::new(),
+        }
+    }
+
+
+    fn buffers(device: &wgpu::Device, glyphs: usize) -> (wgpu::Buffer, wgpu::Buffer)
+    {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text_vertex_buffer"),
+            size: (glyphs * 4 * std::mem::size_of::<VertexUV>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let indices: Vec<u32> = (0..glyphs as u32).flat_map(|g| [0, 1, 2, 0, 2, 3].map(|i| g * 4 + i)).collect();
+        (vertex_buffer, VertexUV::new_index_buffer(device, &indices))
+    }
+
+
+    // lays text out with its top left corner at x, y in window pixels, returns its width and height
+    pub fn queue(&mut self, text: &str, x: f32, y: f32, size: f32, max_width: Option<f32>) -> [f32; 2]
+    {
+        let (glyphs, extent) = layout(&self.font, text, size, max_width);
+        let bitmap = self.atlas.mode == TextMode::Bitmap;
+        for glyph in glyphs
+        {
+            let Some(atlas) = self.atlas.get(&self.font, glyph.id, size) else { continue };
+            let scale = size / atlas.raster;
+            let (mut px, mut py) = (x + glyph.x + atlas.offset[0] * scale, y + glyph.y + atlas.offset[1] * scale);
+            // bitmaps stay sharp only on whole pixels
+            if bitmap { (px, py) = (px.round(), py.round()); }
+            let (w, h) = (atlas.size[0] * scale, atlas.size[1] * scale);
+            let [u0, v0, u1, v1] = atlas.uv;
+            self.vertices.extend_from_slice(&[
+                VertexUV { position: [px, py, 0.0], uv: [u0, v0] },
+                VertexUV { position: [px, py + h, 0.0], uv: [u0, v1] },
+                VertexUV { position: [px + w, py + h, 0.0], uv: [u1, v1] },
+                VertexUV { position: [px + w, py, 0.0], uv: [u1, v0] },
+            ]);
+        }
+        extent
+    }
+
+
+    // uploads new glyphs and the queued quads for a target of width by height pixels
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32)
+    {
+        if self.atlas.dirty
+        {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &self.atlas.pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(ATLAS_SIZE),
+                    rows_per_image: Some(ATLAS_SIZE),
+                },
+                wgpu::Extent3d { width: ATLAS_SIZE, height: ATLAS_SIZE, depth_or_array_layers: 1 },
+            );
+            self.atlas.dirty = false;
+        }
+
+        let glyphs = self.vertices.len() / 4;
+        if glyphs > self.capacity
+        {
+            self.capacity = glyphs.next_power_of_two();
+            (self.vertex_buffer, self.index_buffer) = Self::buffers(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        let params = TextParams
+        {
+            color: self.color,
+            screen: [width as f32, height as f32],
+            sdf: (self.atlas.mode == TextMode::Sdf) as u32,
+            _padding: 0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+
+    // draws everything queued since the last render in one call, over what is in view
+    pub fn render(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView)
+    {
+        let glyphs = self.vertices.len() / 4;
+        self.vertices.clear();
+        if glyphs == 0 { return; }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Text Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..glyphs as u32 * 6, 0, 0..1);
+    }
+}
+// }}}
+
+
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn font() -> FontArc
+    {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/fonts/DejaVuSans.ttf");
+        FontArc::try_from_vec(std::fs::read(path).unwrap()).unwrap()
+    }
+
+    // y of every laid glyph, one entry per line
+    fn lines(glyphs: &[LaidGlyph]) -> Vec<f32>
+    {
+        let mut ys: Vec<f32> = glyphs.iter().map(|g| g.y).collect();
+        ys.dedup();
+        ys
+    }
+
+    #[test]
+    fn words_wrap_before_the_edge()
+    {
+        let font = font();
+        let (glyphs, [width, _]) = layout(&font, "one two three four", 20.0, None);
+        assert_eq!(lines(&glyphs).len(), 1);
+        let (wrapped, [wrapped_width, _]) = layout(&font, "one two three four", 20.0, Some(width * 0.6));
+        assert!(lines(&wrapped).len() > 1);
+        assert!(wrapped_width <= width * 0.6);
+        // every line starts a word at the left edge
+        for (a, b) in wrapped.iter().zip(&wrapped[1..])
+        {
+            if b.y != a.y { assert_eq!(b.x, 0.0); assert_eq!(font.glyph_id(' '), a.id); }
+        }
+    }
+
+    #[test]
+    fn newline_starts_a_line()
+    {
+        let font = font();
+        let (glyphs, [_, height]) = layout(&font, "ab\ncd", 20.0, None);
+        let (_, [_, single]) = layout(&font, "ab", 20.0, None);
+        assert_eq!(glyphs.len(), 4);
+        assert_eq!(lines(&glyphs).len(), 2);
+        assert_eq!(glyphs[2].x, 0.0);
+        assert!(height > single * 1.5);
+    }
+
+    #[test]
+    fn kerning_pulls_pairs_together()
+    {
+        let font = font();
+        let scaled = font.as_scaled(PxScale::from(40.0));
+        let (a, v) = (font.glyph_id('A'), font.glyph_id('V'));
+        assert!(scaled.kern(a, v) < 0.0);
+        let (glyphs, _) = layout(&font, "AV", 40.0, None);
+        assert!((glyphs[1].x - (scaled.h_advance(a) + scaled.kern(a, v))).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_words_break_between_letters()
+    {
+        let font = font();
+        let (glyphs, [width, _]) = layout(&font, "abcdefghijklmnop", 20.0, Some(60.0));
+        assert_eq!(glyphs.len(), 16);
+        assert!(lines(&glyphs).len() >= 3);
+        assert!(width <= 60.0);
+        // nothing is lost or reordered, the pen only goes back at a line start
+        for (a, b) in glyphs.iter().zip(&glyphs[1..])
+        {
+            assert!(b.x > a.x || (b.x == 0.0 && b.y > a.y));
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::core::renderer::DepthMode;
+
 use image::GenericImageView;
 use anyhow::*;
 
@@ -88,6 +90,7 @@ impl Texture {
         size : wgpu::Extent3d,
         label: &str,
         filter: wgpu::FilterMode,
+        depth: DepthMode,
     ) -> Self {
 
         let desc = wgpu::TextureDescriptor {
@@ -112,7 +115,7 @@ impl Texture {
                 mag_filter: filter,
                 min_filter: filter,
                 mipmap_filter: filter,
-                compare: Some(wgpu::CompareFunction::LessEqual), // 5.
+                compare: Some(depth.sampler_compare()), // 5.
                 lod_min_clamp: 0.0,
                 lod_max_clamp: 100.0,
                 ..Default::default()